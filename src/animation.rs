@@ -0,0 +1,163 @@
+use crate::chart::{self, Axis, ChartSpec, Series, SeriesData, Theme, PALETTE};
+use crate::daily;
+use crate::stats::{self, Bandwidth, Kernel};
+use crate::units;
+use crate::TempData;
+
+/*
+Animations stepping through a year, for presentations. Every frame is an
+ordinary ChartSpec so frames look like the still charts, and the axes stay
+fixed across frames so nothing jumps around. Two kinds:
+    - Scatter: the temperatures seen so far, filling in as the year goes on.
+    - Distribution: the density of the last `window` minutes, which shows the
+      spread of temperatures widening and drifting with the seasons.
+Several stations can be overlaid, each in its own colour.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnimationMode {
+    Scatter,
+    Distribution,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AnimationSettings {
+    pub mode: AnimationMode,
+    pub step: u32, // Minutes between frames
+    pub window: u32, // Minutes of data in each Distribution frame
+    pub frames_per_second: u32,
+    pub size: (u32, u32),
+    pub theme: Theme,
+}
+
+impl AnimationSettings {
+    // One frame per week at 10 frames per second, with a 30 day window
+    pub fn new(mode: AnimationMode) -> AnimationSettings {
+        AnimationSettings {
+            mode,
+            step: 7 * 1440,
+            window: 30 * 1440,
+            frames_per_second: 10,
+            size: (1280, 720),
+            theme: Theme { font_size: 46, margin: 30, ..Theme::dark() },
+        }
+    }
+}
+
+// Lowest and highest temperature of any station, °C
+fn temperature_range(stations: &[(&str, &[TempData])]) -> (f64, f64) {
+    let values = stations.iter().flat_map(|(_, temps)| temps.iter().map(|t| t.temp10.degrees()));
+    let (low, high) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), v| (low.min(v), high.max(v)));
+    if low.is_finite() {
+        (low - 2.0, high + 2.0)
+    } else {
+        (0.0, 1.0)
+    }
+}
+
+// Minute of the year at the end of every frame
+fn frame_ends(year: u32, step: u32) -> Vec<u32> {
+    let minutes = if daily::is_leap_year(year) { 366 * 1440 } else { 365 * 1440 };
+    let step = step.max(1);
+    (1..).map(|i| i * step).take_while(|&end| end < minutes + step).map(|end| end.min(minutes)).collect()
+}
+
+fn frame_spec(x: Axis, y: Axis, series: Vec<Series>, title: String, settings: &AnimationSettings) -> ChartSpec {
+    let mut spec = ChartSpec::new(x, y);
+    spec.size = settings.size;
+    spec.theme = settings.theme;
+    spec.title = Some(title);
+    spec.legend = series.len() > 1;
+    spec.series = series;
+    spec
+}
+
+fn scatter_frames(stations: &[(&str, &[TempData])], year: u32, settings: &AnimationSettings) -> Vec<ChartSpec> {
+    let (low, high) = temperature_range(stations);
+    let ends = frame_ends(year, settings.step);
+    let last = ends.last().copied().unwrap_or(0);
+    ends.into_iter()
+        .map(|end| {
+            let series = stations
+                .iter()
+                .zip(PALETTE.iter().cycle())
+                .map(|((name, temps), color)| Series {
+                    name: name.to_string(),
+                    color: *color,
+                    data: SeriesData::Scatter {
+                        points: temps
+                            .iter()
+                            .filter(|t| t.minute_of_year < end)
+                            .map(|t| (t.minute_of_year as f64, t.temp10.display()))
+                            .collect(),
+                        radius: 2,
+                    },
+                })
+                .collect();
+            let title = daily::format_date(year, ((end - 1) / 1440) as usize);
+            frame_spec(chart::time_axis(0, last), chart::temperature_axis(low..high), series, title, settings)
+        })
+        .collect()
+}
+
+fn distribution_frames(stations: &[(&str, &[TempData])], year: u32, settings: &AnimationSettings) -> Vec<ChartSpec> {
+    let (low, high) = temperature_range(stations);
+    // Curves for every frame first, since the y axis has to fit the highest
+    // peak of any of them
+    let curves: Vec<_> = frame_ends(year, settings.step)
+        .into_iter()
+        .map(|end| {
+            let start = end.saturating_sub(settings.window);
+            let curves: Vec<Vec<(f64, f64)>> = stations
+                .iter()
+                .map(|(_, temps)| {
+                    let values: Vec<(f64, f64)> = temps
+                        .iter()
+                        .filter(|t| (start..end).contains(&t.minute_of_year))
+                        .map(|t| (t.temp10.display(), t.duration as f64))
+                        .collect();
+                    stats::kde_curve(&values, Kernel::Gaussian, Bandwidth::Silverman, 300)
+                })
+                .collect();
+            (end, curves)
+        })
+        .collect();
+    let peak = curves.iter().flat_map(|(_, c)| c.iter().flatten()).map(|p| p.1).fold(0.0, f64::max);
+    let days = settings.window / 1440;
+    curves
+        .into_iter()
+        .map(|(end, curves)| {
+            let series = stations
+                .iter()
+                .zip(curves)
+                .zip(PALETTE.iter().cycle())
+                .map(|(((name, _), points), color)| Series {
+                    name: name.to_string(),
+                    color: *color,
+                    data: SeriesData::Line { points, max_gap: None },
+                })
+                .collect();
+            let x = chart::temperature_axis(low..high);
+            let y = Axis {
+                range: 0.0..peak.max(1e-3) * 1.1,
+                description: format!("Density (per {})", units::symbol()),
+                labels: 8,
+                formatter: |d| format!("{:.2}", d),
+            };
+            let title = format!("{} days to {}", days, daily::format_date(year, ((end - 1) / 1440) as usize));
+            frame_spec(x, y, series, title, settings)
+        })
+        .collect()
+}
+
+// Frames of the animation, each station being one year of temperatures
+pub fn frames(stations: &[(&str, &[TempData])], year: u32, settings: &AnimationSettings) -> Vec<ChartSpec> {
+    match settings.mode {
+        AnimationMode::Scatter => scatter_frames(stations, year, settings),
+        AnimationMode::Distribution => distribution_frames(stations, year, settings),
+    }
+}
+
+pub fn animate(stations: &[(&str, &[TempData])], year: u32, settings: &AnimationSettings, path: &str) -> chart::ChartResult {
+    chart::to_gif(&frames(stations, year, settings), path, settings.frames_per_second)
+}
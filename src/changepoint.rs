@@ -0,0 +1,377 @@
+use crate::isd::LocationChange;
+use crate::stats;
+use crate::units;
+use crate::trend::SeriesPoint;
+
+/*
+Change point detection. Long ISD records have jumps from station moves and
+sensor swaps which would otherwise show up as strange weather. Breakpoints in
+the mean of an anomaly series (eg. trend::Aggregate::MonthlyAnomaly) are found
+with either PELT (optimal partitioning with pruning) or binary segmentation,
+and the single most likely break is tested with the Standard Normal
+Homogeneity Test (SNHT). Breaks are then matched against changes in the
+location the station reports in the ISD control section.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Method {
+    Pelt,
+    BinarySegmentation,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Breakpoint {
+    pub time: f64, // Time of the first point of the new segment
+    pub shift: f64, // Mean after minus mean before, °C
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SnhtResult {
+    pub time: f64,
+    pub statistic: f64,
+    pub critical: f64, // 95% critical value for this length
+    pub significant: bool,
+}
+
+// Robust estimate of the noise standard deviation from the median absolute
+// first difference, which a few shifts in the mean barely affect.
+fn noise_scale(values: &[f64]) -> f64 {
+    let differences: Vec<f64> = values.windows(2).map(|w| (w[1] - w[0]).abs()).collect();
+    let scale = stats::median(&differences) / (0.6745 * std::f64::consts::SQRT_2);
+    if scale > 0.0 {
+        scale
+    } else {
+        1.0
+    }
+}
+
+// Prefix sums so that the cost of any segment is O(1)
+struct SegmentCost {
+    sum: Vec<f64>,
+    sum_squares: Vec<f64>,
+    variance: f64,
+}
+
+impl SegmentCost {
+    fn new(values: &[f64]) -> SegmentCost {
+        let mut sum = vec![0.0];
+        let mut sum_squares = vec![0.0];
+        for v in values {
+            sum.push(sum.last().unwrap() + v);
+            sum_squares.push(sum_squares.last().unwrap() + v * v);
+        }
+        let scale = noise_scale(values);
+        SegmentCost { sum, sum_squares, variance: scale * scale }
+    }
+
+    // Residual sum of squares of values[start..end] around their mean, in
+    // units of the noise variance
+    fn cost(&self, start: usize, end: usize) -> f64 {
+        let n = (end - start) as f64;
+        let s = self.sum[end] - self.sum[start];
+        let ss = self.sum_squares[end] - self.sum_squares[start];
+        (ss - s * s / n) / self.variance
+    }
+
+    fn mean(&self, start: usize, end: usize) -> f64 {
+        (self.sum[end] - self.sum[start]) / (end - start) as f64
+    }
+}
+
+// Default penalty per breakpoint, the BIC for a change in mean
+pub fn default_penalty(n: usize) -> f64 {
+    2.0 * (n as f64).ln()
+}
+
+// Finds the breakpoints in `series`. Segments are at least `min_length` points
+// long, and every breakpoint must reduce the cost by more than `penalty`.
+pub fn detect_breakpoints(series: &[SeriesPoint], method: Method, penalty: f64, min_length: usize) -> Vec<Breakpoint> {
+    let values: Vec<f64> = series.iter().map(|p| p.1).collect();
+    let min_length = min_length.max(1);
+    if values.len() < 2 * min_length {
+        return Vec::new();
+    }
+    let costs = SegmentCost::new(&values);
+    let mut indices = match method {
+        Method::Pelt => pelt(&costs, values.len(), penalty, min_length),
+        Method::BinarySegmentation => {
+            let mut indices = Vec::new();
+            binary_segmentation(&costs, 0, values.len(), penalty, min_length, &mut indices);
+            indices
+        }
+    };
+    indices.sort_unstable();
+
+    let mut breakpoints = Vec::with_capacity(indices.len());
+    for (i, &index) in indices.iter().enumerate() {
+        let start = if i == 0 { 0 } else { indices[i - 1] };
+        let end = indices.get(i + 1).copied().unwrap_or(values.len());
+        breakpoints.push(Breakpoint {
+            time: series[index].0,
+            shift: costs.mean(index, end) - costs.mean(start, index),
+        });
+    }
+    breakpoints
+}
+
+// Killick, Fearnhead and Eckley (2012)
+fn pelt(costs: &SegmentCost, n: usize, penalty: f64, min_length: usize) -> Vec<usize> {
+    let mut best = vec![f64::INFINITY; n + 1];
+    let mut last_change = vec![0; n + 1];
+    best[0] = -penalty;
+    let mut candidates: Vec<usize> = vec![0];
+    for end in min_length..=n {
+        for &start in &candidates {
+            if end - start < min_length {
+                continue;
+            }
+            let value = best[start] + costs.cost(start, end) + penalty;
+            if value < best[end] {
+                best[end] = value;
+                last_change[end] = start;
+            }
+        }
+        let threshold = best[end];
+        candidates.retain(|&start| end - start < min_length || best[start] + costs.cost(start, end) <= threshold);
+        if end + min_length <= n {
+            candidates.push(end);
+        }
+    }
+    let mut indices = Vec::new();
+    let mut end = n;
+    while end > 0 {
+        let start = last_change[end];
+        if start > 0 {
+            indices.push(start);
+        }
+        end = start;
+    }
+    indices
+}
+
+fn binary_segmentation(
+    costs: &SegmentCost,
+    start: usize,
+    end: usize,
+    penalty: f64,
+    min_length: usize,
+    indices: &mut Vec<usize>,
+) {
+    if end - start < 2 * min_length {
+        return;
+    }
+    let whole = costs.cost(start, end);
+    let best = (start + min_length..=end - min_length)
+        .map(|split| (split, whole - costs.cost(start, split) - costs.cost(split, end)))
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+    if let Some((split, gain)) = best {
+        if gain > penalty {
+            indices.push(split);
+            binary_segmentation(costs, start, split, penalty, min_length, indices);
+            binary_segmentation(costs, split, end, penalty, min_length, indices);
+        }
+    }
+}
+
+// Number of white noise series simulated for each SNHT critical value
+const SNHT_SIMULATIONS: usize = 20_000;
+
+// Largest SNHT statistic of a series and the index of the first value after
+// the shift it points to. None if the series is too short or constant.
+fn snht_statistic(values: &[f64]) -> Option<(usize, f64)> {
+    let n = values.len();
+    if n < 3 {
+        return None;
+    }
+    let mean = stats::mean(values);
+    let sd = stats::variance(values).sqrt();
+    if sd == 0.0 {
+        return None;
+    }
+    let total: f64 = values.iter().map(|v| (v - mean) / sd).sum();
+    let mut prefix = 0.0;
+    let mut best = (0, f64::NEG_INFINITY);
+    for k in 1..n {
+        prefix += (values[k - 1] - mean) / sd;
+        let before = prefix / k as f64;
+        let after = (total - prefix) / (n - k) as f64;
+        let statistic = k as f64 * before * before + (n - k) as f64 * after * after;
+        if statistic > best.1 {
+            best = (k, statistic);
+        }
+    }
+    Some(best)
+}
+
+// 95% critical value of the SNHT statistic for a series of length `n`. The
+// published tables (Alexandersson 1986, Khaliq and Ouarda 2007) only list a
+// handful of lengths and disagree in the second decimal, so this repeats
+// Khaliq and Ouarda's Monte Carlo procedure instead: the 95th percentile of
+// the statistic over many series of Gaussian white noise of the same length.
+// The seed is fixed, so the value is the same on every run.
+pub fn snht_critical_value(n: usize) -> f64 {
+    let mut random = stats::Random::new(n as u64);
+    let mut noise = vec![0.0; n];
+    let statistics: Vec<f64> = (0..SNHT_SIMULATIONS)
+        .filter_map(|_| {
+            noise.iter_mut().for_each(|v| *v = random.normal());
+            snht_statistic(&noise).map(|(_, statistic)| statistic)
+        })
+        .collect();
+    stats::quantile(&statistics, 0.95)
+}
+
+// Alexandersson's Standard Normal Homogeneity Test for a single shift
+pub fn snht(series: &[SeriesPoint]) -> Option<SnhtResult> {
+    let values: Vec<f64> = series.iter().map(|p| p.1).collect();
+    let (index, statistic) = snht_statistic(&values)?;
+    let critical = snht_critical_value(series.len());
+    Some(SnhtResult {
+        time: series[index].0,
+        statistic,
+        critical,
+        significant: statistic > critical,
+    })
+}
+
+// Pairs every breakpoint with the closest change in reported location within
+// `tolerance` years, if there is one.
+pub fn cross_reference(
+    breakpoints: &[Breakpoint],
+    changes: &[LocationChange],
+    tolerance: f64,
+) -> Vec<(Breakpoint, Option<LocationChange>)> {
+    breakpoints
+        .iter()
+        .map(|b| {
+            let closest = changes
+                .iter()
+                .filter(|c| (c.time - b.time).abs() <= tolerance)
+                .min_by(|x, y| (x.time - b.time).abs().total_cmp(&(y.time - b.time).abs()));
+            (*b, closest.copied())
+        })
+        .collect()
+}
+
+// Great circle distance between two locations in km
+fn distance(change: &LocationChange) -> f64 {
+    let (lat1, lat2) = (change.from.latitude.to_radians(), change.to.latitude.to_radians());
+    let delta_lat = lat2 - lat1;
+    let delta_lon = (change.to.longitude - change.from.longitude).to_radians();
+    let a = (delta_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (delta_lon / 2.0).sin().powi(2);
+    2.0 * 6371.0 * a.sqrt().asin()
+}
+
+fn format_time(time: f64) -> String {
+    let year = time.floor();
+    let month = ((time - year) * 12.0).floor() as usize + 1;
+    format!("{}-{:02}", year as u32, month.min(12))
+}
+
+pub fn print_breakpoints(
+    station: &str,
+    matched: &[(Breakpoint, Option<LocationChange>)],
+    changes: &[LocationChange],
+    snht: Option<&SnhtResult>,
+) {
+    println!("Breakpoints at {}: {}", station, matched.len());
+    for (breakpoint, change) in matched {
+        let cause = match change {
+            Some(c) => format!(
+                "station moved {:.1} km and {:+} m on {}",
+                distance(c),
+                c.to.elevation - c.from.elevation,
+                format_time(c.time)
+            ),
+            None => "no location change, possibly an instrument change".to_string(),
+        };
+        println!(
+            "  {}: shift {:+.2} {} ({})",
+            format_time(breakpoint.time),
+            units::difference(breakpoint.shift),
+            units::symbol(),
+            cause
+        );
+    }
+    if let Some(result) = snht {
+        println!(
+            "  SNHT: T = {:.2} at {} (95% critical value {:.2}), {}",
+            result.statistic,
+            format_time(result.time),
+            result.critical,
+            if result.significant { "inhomogeneous" } else { "homogeneous" }
+        );
+    }
+    println!("  Location changes in the ISD control section: {}", changes.len());
+    for change in changes {
+        println!(
+            "    {}: ({:.3}, {:.3}, {} m) -> ({:.3}, {:.3}, {} m)",
+            format_time(change.time),
+            change.from.latitude,
+            change.from.longitude,
+            change.from.elevation,
+            change.to.latitude,
+            change.to.longitude,
+            change.to.elevation
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snht_critical_values_match_the_published_tables() {
+        // Alexandersson (1986) and Khaliq and Ouarda (2007) agree to within
+        // about 0.1 at these lengths
+        for (n, published) in [(10, 5.70), (20, 6.95), (50, 8.45), (100, 9.15)] {
+            let simulated = snht_critical_value(n);
+            assert!((simulated - published).abs() < 0.25, "n = {}: {} against {}", n, simulated, published);
+        }
+        assert_eq!(snht_critical_value(30), snht_critical_value(30));
+    }
+
+    // Monthly noise with a 1 °C jump at the start of 2010
+    fn stepped_series() -> Vec<SeriesPoint> {
+        let mut random = stats::Random::new(7);
+        (0..240)
+            .map(|month| {
+                let time = 2000.0 + month as f64 / 12.0;
+                let shift = if month >= 120 { 1.0 } else { 0.0 };
+                (time, shift + 0.3 * random.normal())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn both_methods_find_a_single_step() {
+        let series = stepped_series();
+        for method in [Method::Pelt, Method::BinarySegmentation] {
+            let breakpoints = detect_breakpoints(&series, method, default_penalty(series.len()), 12);
+            assert_eq!(breakpoints.len(), 1, "{:?}: {:?}", method, breakpoints);
+            assert!((breakpoints[0].time - 2010.0).abs() <= 2.0 / 12.0, "{:?}", breakpoints);
+            assert!((breakpoints[0].shift - 1.0).abs() < 0.15, "{:?}", breakpoints);
+        }
+    }
+
+    #[test]
+    fn no_breakpoints_in_noise() {
+        let mut random = stats::Random::new(11);
+        let series: Vec<SeriesPoint> = (0..240).map(|month| (2000.0 + month as f64 / 12.0, 0.3 * random.normal())).collect();
+        for method in [Method::Pelt, Method::BinarySegmentation] {
+            assert!(detect_breakpoints(&series, method, default_penalty(series.len()), 12).is_empty());
+        }
+    }
+
+    #[test]
+    fn snht_flags_the_step() {
+        let result = snht(&stepped_series()).unwrap();
+        assert!(result.significant);
+        assert!((result.time - 2010.0).abs() <= 2.0 / 12.0, "{:?}", result);
+        // Without the step the statistic stays below the critical value
+        let flat: Vec<SeriesPoint> = stepped_series().into_iter().map(|(t, v)| (t, if t >= 2010.0 { v - 1.0 } else { v })).collect();
+        assert!(!snht(&flat).unwrap().significant);
+    }
+}
+
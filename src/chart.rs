@@ -0,0 +1,926 @@
+use std::ops::Range;
+
+use image::codecs::png::PngEncoder;
+use image::{ColorType, ImageEncoder};
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use plotters::style::text_anchor::{HPos, Pos, VPos};
+
+use crate::daily::{self, DailyVariable};
+use crate::normals::DailyNormal;
+use crate::stats::{self, Bandwidth, Kernel};
+use crate::units;
+use crate::wind::{self, WindRose};
+use crate::TempData;
+
+/*
+Every chart goes through a ChartSpec: what to draw (a list of series), where
+(output path and size), and how it looks (theme and title). Scatter, line,
+histogram, band, box, violin, bar and cell series can be mixed freely on the
+same axes, and a colour bar can be added beside them for charts which encode
+values as colour. The wind rose is the one exception, being polar. Errors from
+plotters are returned rather than unwrapped so a bad path or missing font
+doesn't take the whole program down.
+
+Anything implementing Chart can be written to a PNG or SVG file, or rendered
+into memory (SVG text or PNG bytes) so a web handler can return it without
+touching the filesystem.
+*/
+
+pub type ChartResult = Result<(), Box<dyn std::error::Error>>;
+
+pub trait Chart {
+    fn size(&self) -> (u32, u32);
+
+    fn draw<DB: DrawingBackend>(&self, root: &DrawingArea<DB, Shift>) -> ChartResult
+    where
+        DB::ErrorType: 'static;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Png,
+    Svg,
+}
+
+impl Format {
+    // PNG unless the path ends in .svg
+    pub fn from_path(path: &str) -> Format {
+        if path.to_ascii_lowercase().ends_with(".svg") {
+            Format::Svg
+        } else {
+            Format::Png
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Format::Png => "image/png",
+            Format::Svg => "image/svg+xml",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Theme {
+    pub background: RGBColor,
+    pub foreground: RGBColor,
+    pub font_size: u32,
+    pub margin: u32,
+}
+
+impl Theme {
+    // White on black, which is what every chart used to be
+    pub fn dark() -> Theme {
+        Theme { background: BLACK, foreground: WHITE, font_size: 70, margin: 50 }
+    }
+
+    pub fn light() -> Theme {
+        Theme { background: WHITE, foreground: BLACK, font_size: 70, margin: 50 }
+    }
+}
+
+pub enum SeriesData {
+    Scatter { points: Vec<(f64, f64)>, radius: u32 },
+    // Points further apart than `max_gap` along x aren't joined, so missing
+    // data shows as a break in the line. None joins every point.
+    Line { points: Vec<(f64, f64)>, max_gap: Option<f64> },
+    // (value, weight) pairs sorted into bins of `bin_width`. With `normalise`
+    // the bar heights are the fraction of the total weight in each bin.
+    Histogram { values: Vec<(f64, f64)>, bin_width: f64, normalise: bool },
+    // (x, lower, upper), shaded between lower and upper
+    Band { points: Vec<(f64, f64, f64)> },
+    // (x, [lower whisker, lower quartile, median, upper quartile, upper
+    // whisker]), each box `width` wide
+    Boxes { boxes: Vec<(f64, [f64; 5])>, width: f64 },
+    // (x, (y, density) pairs ascending in y), mirrored around x and scaled so
+    // the widest point is `width` across
+    Violins { violins: Vec<(f64, Vec<(f64, f64)>)>, width: f64 },
+    // (x start, x end, height, colour), each bar rising or falling from 0
+    Bars { bars: Vec<(f64, f64, f64, RGBColor)> },
+    // (x start, y start, x end, y end, colour), eg. the days of a heatmap
+    Cells { cells: Vec<(f64, f64, f64, f64, RGBColor)> },
+}
+
+// Series with an empty name are left out of the legend
+pub struct Series {
+    pub name: String,
+    pub color: RGBColor,
+    pub data: SeriesData,
+}
+
+pub struct Axis {
+    pub range: Range<f64>,
+    pub description: String,
+    pub labels: usize,
+    pub formatter: fn(&f64) -> String,
+}
+
+// Colours for series that need telling apart, eg. one per station. Picked to
+// stay distinguishable for colour blind readers (Okabe-Ito).
+pub const PALETTE: [RGBColor; 8] = [
+    RGBColor(230, 159, 0),
+    RGBColor(86, 180, 233),
+    RGBColor(0, 158, 115),
+    RGBColor(240, 228, 66),
+    RGBColor(213, 94, 0),
+    RGBColor(204, 121, 167),
+    RGBColor(0, 114, 178),
+    RGBColor(153, 153, 153),
+];
+
+// A continuous colour scale, interpolated linearly between evenly spaced stops
+#[derive(Clone, Copy, Debug)]
+pub struct ColourMap {
+    pub stops: &'static [RGBColor],
+}
+
+impl ColourMap {
+    // Colour at `t` between 0 and 1 (clamped)
+    pub fn colour(&self, t: f64) -> RGBColor {
+        let t = if t.is_nan() { 0.5 } else { t.clamp(0.0, 1.0) };
+        let position = t * (self.stops.len() - 1) as f64;
+        let index = (position.floor() as usize).min(self.stops.len() - 2);
+        let fraction = position - index as f64;
+        let (a, b) = (self.stops[index], self.stops[index + 1]);
+        let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * fraction).round() as u8;
+        RGBColor(mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+    }
+
+    // Colour of `value` on a scale centred on zero which saturates at ±limit
+    pub fn diverging(&self, value: f64, limit: f64) -> RGBColor {
+        self.colour(0.5 + value / (2.0 * limit))
+    }
+}
+
+// Cold to warm through white, for anomalies (ColorBrewer RdBu, reversed)
+pub const BLUE_WHITE_RED: ColourMap = ColourMap {
+    stops: &[
+        RGBColor(33, 102, 172),
+        RGBColor(103, 169, 207),
+        RGBColor(209, 229, 240),
+        RGBColor(247, 247, 247),
+        RGBColor(253, 219, 199),
+        RGBColor(239, 138, 98),
+        RGBColor(178, 24, 43),
+    ],
+};
+
+// Ed Hawkins' warming stripes palette, from deep blue to dark red
+pub const STRIPES: ColourMap = ColourMap {
+    stops: &[
+        RGBColor(8, 48, 107),
+        RGBColor(8, 81, 156),
+        RGBColor(33, 113, 181),
+        RGBColor(66, 146, 198),
+        RGBColor(107, 174, 214),
+        RGBColor(158, 202, 225),
+        RGBColor(198, 219, 239),
+        RGBColor(222, 235, 247),
+        RGBColor(254, 224, 210),
+        RGBColor(252, 187, 161),
+        RGBColor(252, 146, 114),
+        RGBColor(251, 106, 74),
+        RGBColor(239, 59, 44),
+        RGBColor(203, 24, 29),
+        RGBColor(165, 15, 21),
+        RGBColor(103, 0, 13),
+    ],
+};
+
+// Diverging without red and green, for readers who can't tell them apart
+// (ColorBrewer PuOr, reversed)
+pub const PURPLE_ORANGE: ColourMap = ColourMap {
+    stops: &[
+        RGBColor(84, 39, 136),
+        RGBColor(153, 142, 195),
+        RGBColor(216, 218, 235),
+        RGBColor(247, 247, 247),
+        RGBColor(254, 224, 182),
+        RGBColor(241, 163, 64),
+        RGBColor(179, 88, 6),
+    ],
+};
+
+// Longest stretch without observations, in minutes, which an overlay line
+// still bridges. Longer gaps leave a break in the line.
+const OVERLAY_MAX_GAP: f64 = 180.0;
+
+// How stations are compared on an overlay chart
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverlayMode {
+    // Temperatures as observed
+    Raw,
+    // Each station's temperatures minus its mean over the window, divided by
+    // its standard deviation, so stations with very different climates share
+    // one scale
+    Anomaly,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Grid {
+    Full,
+    Horizontal, // Lines across at the y labels only
+    Off,
+}
+
+// Scale drawn to the right of the chart, from `range` (in display units) at
+// the bottom to the top
+#[derive(Clone, Debug)]
+pub struct ColourBar {
+    pub map: ColourMap,
+    pub range: Range<f64>,
+    pub description: String,
+}
+
+pub struct ChartSpec {
+    pub title: Option<String>,
+    pub size: (u32, u32),
+    pub theme: Theme,
+    pub x: Axis,
+    pub y: Axis,
+    pub series: Vec<Series>,
+    pub legend: bool,
+    pub legend_position: SeriesLabelPosition,
+    pub grid: Grid,
+    pub axes: bool, // Without axes only the series (and title) are drawn
+    pub colour_bar: Option<ColourBar>,
+}
+
+fn plain_label(value: &f64) -> String {
+    format!("{}", value)
+}
+
+fn degree_label(value: &f64) -> String {
+    format!("{:.0}", value)
+}
+
+// Axis for temperatures between two values in °C, shown in the chosen unit
+pub fn temperature_axis(celsius: Range<f64>) -> Axis {
+    Axis {
+        range: units::temperature(celsius.start)..units::temperature(celsius.end),
+        description: format!("Temperature ({})", units::symbol()),
+        labels: 12,
+        formatter: degree_label,
+    }
+}
+
+// Axis for a window of minutes of the year, labelled in days of the year or,
+// for a single day, hours
+pub fn time_axis(start: u32, end: u32) -> Axis {
+    let days = (end - start) as f64 / 1440.0;
+    if days > 14.0 {
+        Axis {
+            range: start as f64..end as f64,
+            description: "Progress in Year (Days)".to_string(),
+            labels: 15,
+            formatter: |minute| format!("{}", (*minute / 1440.0).floor()),
+        }
+    } else if days > 1.0 {
+        Axis {
+            range: start as f64..end as f64,
+            description: "Progress in Year (Days)".to_string(),
+            labels: 14,
+            formatter: |minute| format!("{:.1}", *minute / 1440.0),
+        }
+    } else {
+        Axis {
+            range: start as f64..end as f64,
+            description: "Time of Day (Hours)".to_string(),
+            labels: 24,
+            formatter: |minute| format!("{}", ((*minute % 1440.0) / 60.0).floor()),
+        }
+    }
+}
+
+impl Axis {
+    pub fn new(range: Range<f64>, description: &str) -> Axis {
+        Axis { range, description: description.to_string(), labels: 10, formatter: plain_label }
+    }
+}
+
+impl ChartSpec {
+    pub fn new(x: Axis, y: Axis) -> ChartSpec {
+        ChartSpec {
+            title: None,
+            size: (1920, 1080),
+            theme: Theme::dark(),
+            x,
+            y,
+            series: Vec::new(),
+            legend: false,
+            legend_position: SeriesLabelPosition::UpperRight,
+            grid: Grid::Full,
+            axes: true,
+            colour_bar: None,
+        }
+    }
+
+    // GitHub style calendar of a year: one column per week, one row per day of
+    // the week with Sunday at the top. `colours` is indexed by day of year;
+    // days which are None are left empty.
+    pub fn calendar(year: u32, colours: &[Option<RGBColor>]) -> ChartSpec {
+        let x = Axis { range: 0.0..54.0, description: "Week".to_string(), labels: 12, formatter: |week| format!("{:.0}", week) };
+        let y = Axis {
+            range: -0.5..6.5,
+            description: String::new(),
+            labels: 7,
+            formatter: |row| match ["Sat", "Fri", "Thu", "Wed", "Tue", "Mon", "Sun"].get(row.round() as usize) {
+                Some(name) if (row - row.round()).abs() < 1e-6 => name.to_string(),
+                _ => String::new(),
+            },
+        };
+        let days_in_year = if daily::is_leap_year(year) { 366 } else { 365 };
+        let first_weekday = daily::day_of_week(year, 1, 1);
+        let cells = (0..days_in_year.min(colours.len()))
+            .filter_map(|day| {
+                let slot = day + first_weekday;
+                let (week, row) = ((slot / 7) as f64, (6 - slot % 7) as f64);
+                Some((week, row - 0.5, week + 1.0, row + 0.5, colours[day]?))
+            })
+            .collect();
+        let mut spec = ChartSpec::new(x, y);
+        spec.size = (1920, 400);
+        spec.theme = Theme { font_size: 48, margin: 20, ..Theme::dark() };
+        spec.grid = Grid::Off;
+        spec.series.push(Series { name: String::new(), color: WHITE, data: SeriesData::Cells { cells } });
+        spec
+    }
+
+    // Scatter plot of every temperature between two minutes of the year. This
+    // replaces the old year, week and day plots, which only differed in the
+    // window they showed.
+    pub fn temperatures(temps: &[TempData], start: u32, end: u32) -> ChartSpec {
+        let days = (end - start) as f64 / 1440.0;
+        let mut spec = ChartSpec::new(time_axis(start, end), temperature_axis(-15.0..50.0));
+        spec.series.push(Series {
+            name: "Temperature".to_string(),
+            color: WHITE,
+            data: SeriesData::Scatter {
+                points: temps
+                    .iter()
+                    .filter(|t| (start..end).contains(&t.minute_of_year))
+                    .map(|t| (t.minute_of_year as f64, t.temp10.display()))
+                    .collect(),
+                radius: if days > 14.0 { 2 } else { 3 },
+            },
+        });
+        spec
+    }
+
+    // One line per station over the same window of the year, with a legend
+    // naming each station
+    pub fn overlay(stations: &[(&str, &[TempData])], start: u32, end: u32, mode: OverlayMode) -> ChartSpec {
+        let lines: Vec<Vec<(f64, f64)>> = stations
+            .iter()
+            .map(|(_, temps)| {
+                let in_window: Vec<&TempData> = temps.iter().filter(|t| (start..end).contains(&t.minute_of_year)).collect();
+                match mode {
+                    OverlayMode::Raw => in_window.iter().map(|t| (t.minute_of_year as f64, t.temp10.display())).collect(),
+                    OverlayMode::Anomaly => {
+                        let weight: f64 = in_window.iter().map(|t| t.duration as f64).sum::<f64>().max(1.0);
+                        let mean = in_window.iter().map(|t| t.temp10.degrees() * t.duration as f64).sum::<f64>() / weight;
+                        let variance = in_window
+                            .iter()
+                            .map(|t| (t.temp10.degrees() - mean).powi(2) * t.duration as f64)
+                            .sum::<f64>()
+                            / weight;
+                        let sd = if variance > 0.0 { variance.sqrt() } else { 1.0 };
+                        in_window
+                            .iter()
+                            .map(|t| (t.minute_of_year as f64, (t.temp10.degrees() - mean) / sd))
+                            .collect()
+                    }
+                }
+            })
+            .collect();
+
+        let values = lines.iter().flatten().map(|p| p.1);
+        let (low, high) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), v| (low.min(v), high.max(v)));
+        let (low, high) = if low.is_finite() { (low, high) } else { (0.0, 1.0) };
+        let padding = ((high - low) * 0.05).max(0.5);
+        let y = match mode {
+            OverlayMode::Raw => Axis {
+                range: low - padding..high + padding,
+                description: format!("Temperature ({})", units::symbol()),
+                labels: 12,
+                formatter: degree_label,
+            },
+            OverlayMode::Anomaly => Axis {
+                range: low - padding..high + padding,
+                description: "Standardised anomaly (σ)".to_string(),
+                labels: 12,
+                formatter: |sigma| format!("{:+.1}", sigma),
+            },
+        };
+        let mut spec = ChartSpec::new(time_axis(start, end), y);
+        spec.series = stations
+            .iter()
+            .zip(lines)
+            .zip(PALETTE.iter().cycle())
+            .map(|(((name, _), points), color)| Series {
+                name: name.to_string(),
+                color: *color,
+                data: SeriesData::Line { points, max_gap: Some(OVERLAY_MAX_GAP) },
+            })
+            .collect();
+        spec.legend = true;
+        spec
+    }
+
+    // "How strange was this year": the normal of `variable` as a line with
+    // ±1σ and ±2σ bands, record highs and lows as dots, and the year's own
+    // daily values on top. Days more than 2σ from normal are highlighted, and
+    // the x axis is the calendar day (see daily::calendar_day).
+    pub fn climatology(normals: &[Option<DailyNormal>], temps: &[TempData], year: u32, variable: DailyVariable) -> ChartSpec {
+        let normal_days: Vec<(f64, &DailyNormal)> =
+            normals.iter().enumerate().filter_map(|(day, n)| Some((day as f64, n.as_ref()?))).collect();
+        let band = |sigmas: f64| -> Vec<(f64, f64, f64)> {
+            normal_days
+                .iter()
+                .map(|(day, n)| {
+                    let spread = sigmas * n.standard_deviation;
+                    (*day, units::temperature(n.mean - spread), units::temperature(n.mean + spread))
+                })
+                .collect()
+        };
+
+        let mut typical = Vec::new();
+        let mut unusual = Vec::new();
+        for (day, stats) in daily::calendar_daily_stats(temps, year).iter().enumerate() {
+            let (Some(stats), Some(normal)) = (stats, normals.get(day).copied().flatten()) else {
+                continue;
+            };
+            let value = stats.get(variable);
+            let point = (day as f64, units::temperature(value));
+            if normal.z_score(value).abs() > 2.0 {
+                unusual.push(point);
+            } else {
+                typical.push(point);
+            }
+        }
+
+        let low = normal_days.iter().map(|(_, n)| n.record_low).fold(f64::INFINITY, f64::min);
+        let high = normal_days.iter().map(|(_, n)| n.record_high).fold(f64::NEG_INFINITY, f64::max);
+        let (low, high) = if low.is_finite() { (low, high) } else { (-15.0, 50.0) };
+        let x = Axis { range: 0.0..366.0, description: "Day of Year".to_string(), labels: 12, formatter: |day| format!("{:.0}", day) };
+        let mut spec = ChartSpec::new(x, temperature_axis(low - 2.0..high + 2.0));
+        let records = |select: fn(&DailyNormal) -> f64| -> Vec<(f64, f64)> {
+            normal_days.iter().map(|(day, n)| (*day, units::temperature(select(n)))).collect()
+        };
+        spec.series = vec![
+            Series { name: "±2σ".to_string(), color: PALETTE[1], data: SeriesData::Band { points: band(2.0) } },
+            Series { name: "±1σ".to_string(), color: PALETTE[1], data: SeriesData::Band { points: band(1.0) } },
+            Series {
+                name: "Normal".to_string(),
+                color: spec.theme.foreground,
+                data: SeriesData::Line {
+                    points: normal_days.iter().map(|(day, n)| (*day, units::temperature(n.mean))).collect(),
+                    max_gap: Some(1.0),
+                },
+            },
+            Series {
+                name: "Record high".to_string(),
+                color: RGBColor(200, 60, 60),
+                data: SeriesData::Scatter { points: records(|n| n.record_high), radius: 3 },
+            },
+            Series {
+                name: "Record low".to_string(),
+                color: RGBColor(90, 120, 220),
+                data: SeriesData::Scatter { points: records(|n| n.record_low), radius: 3 },
+            },
+            Series { name: "Observed".to_string(), color: RGBColor(190, 190, 190), data: SeriesData::Scatter { points: typical, radius: 4 } },
+            Series { name: "Beyond 2σ".to_string(), color: PALETTE[3], data: SeriesData::Scatter { points: unusual, radius: 6 } },
+        ];
+        spec.legend = true;
+        spec
+    }
+
+    // Histogram of temperatures weighted by how long each one lasted. The axes
+    // run a degree past the coldest and warmest readings and a tenth above the
+    // tallest bar.
+    pub fn distribution(temps: &[TempData]) -> ChartSpec {
+        let values: Vec<(f64, f64)> = temps.iter().map(|t| (t.temp10.display(), t.duration as f64)).collect();
+        let bin_width = units::difference(0.1);
+        let low = values.iter().map(|v| v.0).fold(f64::INFINITY, f64::min);
+        let high = values.iter().map(|v| v.0).fold(f64::NEG_INFINITY, f64::max);
+        let mut x = temperature_axis(-15.0..15.0);
+        if low.is_finite() {
+            x.range = low.floor() - 1.0..high.ceil() + 1.0;
+        }
+        let tallest = histogram_bins(&values, bin_width, true).iter().map(|b| b.1).fold(0.0, f64::max);
+        let top = if tallest > 0.0 { tallest * 1.1 } else { 0.1 };
+        let y = Axis { range: 0.0..top, description: "Probability".to_string(), labels: 10, formatter: |p| format!("{:.3}", p) };
+        let mut spec = ChartSpec::new(x, y);
+        spec.series.push(Series {
+            name: "Temperature".to_string(),
+            color: RED,
+            data: SeriesData::Histogram { values, bin_width, normalise: true },
+        });
+        spec
+    }
+
+    // Adds a smooth density curve over every histogram series, scaled to the
+    // bar heights, since bins of a tenth of a degree come out very noisy
+    pub fn add_density(&mut self, kernel: Kernel, rule: Bandwidth) {
+        let curves: Vec<Series> = self
+            .series
+            .iter()
+            .filter_map(|series| match &series.data {
+                SeriesData::Histogram { values, bin_width, normalise } => {
+                    let total: f64 = if *normalise { 1.0 } else { values.iter().map(|v| v.1).sum() };
+                    let points = stats::kde_curve(values, kernel, rule, 500)
+                        .into_iter()
+                        .map(|(x, density)| (x, density * bin_width * total))
+                        .collect();
+                    Some(Series {
+                        name: format!("{} density", series.name),
+                        color: series.color,
+                        data: SeriesData::Line { points, max_gap: None },
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        self.series.extend(curves);
+    }
+}
+
+// Bar heights of a histogram series: (bin start, height)
+fn histogram_bins(values: &[(f64, f64)], bin_width: f64, normalise: bool) -> Vec<(f64, f64)> {
+    let mut bins: Vec<(i64, f64)> = Vec::new();
+    for &(value, weight) in values {
+        // Rounding first stops tenths of a degree landing in the wrong bin
+        let bin = ((value / bin_width) + 1e-9).floor() as i64;
+        match bins.iter_mut().find(|(b, _)| *b == bin) {
+            Some((_, total)) => *total += weight,
+            None => bins.push((bin, weight)),
+        }
+    }
+    let total: f64 = if normalise { bins.iter().map(|b| b.1).sum() } else { 1.0 };
+    bins.iter().map(|&(bin, weight)| (bin as f64 * bin_width, weight / total)).collect()
+}
+
+// Splits a line's points wherever consecutive points are more than `max_gap`
+// apart along x
+fn line_runs(points: &[(f64, f64)], max_gap: Option<f64>) -> Vec<Vec<(f64, f64)>> {
+    let mut runs: Vec<Vec<(f64, f64)>> = Vec::new();
+    for &point in points {
+        match runs.last_mut() {
+            Some(run) if max_gap.is_none_or(|gap| point.0 - run[run.len() - 1].0 <= gap) => run.push(point),
+            _ => runs.push(vec![point]),
+        }
+    }
+    runs
+}
+
+impl Chart for ChartSpec {
+    fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn draw<DB: DrawingBackend>(&self, root: &DrawingArea<DB, Shift>) -> ChartResult
+    where
+        DB::ErrorType: 'static,
+    {
+        draw_spec(root, self)
+    }
+}
+
+fn draw_spec<DB: DrawingBackend>(root: &DrawingArea<DB, Shift>, spec: &ChartSpec) -> ChartResult
+where
+    DB::ErrorType: 'static,
+{
+    let theme = &spec.theme;
+    let foreground = theme.foreground;
+    let background = theme.background;
+    root.fill(&background)?;
+    let plot = match &spec.colour_bar {
+        Some(colour_bar) => {
+            let (width, _) = root.dim_in_pixel();
+            let (plot, bar) = root.split_horizontally(width.saturating_sub(theme.font_size * 22 / 7));
+            draw_colour_bar(&bar, colour_bar, theme)?;
+            plot
+        }
+        None => root.clone(),
+    };
+
+    let mut builder = ChartBuilder::on(&plot);
+    builder.margin(theme.margin);
+    if spec.axes {
+        builder
+            .set_label_area_size(LabelAreaPosition::Bottom, theme.font_size * 2)
+            .set_label_area_size(LabelAreaPosition::Left, theme.font_size * 24 / 7);
+    }
+    if let Some(title) = &spec.title {
+        builder.caption(title, ("sans-serif", theme.font_size * 4 / 7, &foreground));
+    }
+    let mut chart = builder.build_cartesian_2d(spec.x.range.clone(), spec.y.range.clone())?;
+
+    if spec.axes {
+        let mut mesh = chart.configure_mesh();
+        match spec.grid {
+            Grid::Full => &mut mesh,
+            Grid::Horizontal => mesh.disable_x_mesh(),
+            Grid::Off => mesh.disable_mesh(),
+        };
+        mesh.x_labels(spec.x.labels)
+            .x_label_formatter(&spec.x.formatter)
+            .x_desc(spec.x.description.as_str())
+            .y_labels(spec.y.labels)
+            .y_label_formatter(&spec.y.formatter)
+            .y_desc(spec.y.description.as_str())
+            .axis_desc_style(("sans-serif", theme.font_size, &foreground))
+            .axis_style(foreground)
+            .label_style(("sans-serif", theme.font_size, &foreground))
+            .draw()?;
+    }
+
+    for series in &spec.series {
+        let color = series.color;
+        let annotation = match &series.data {
+            SeriesData::Scatter { points, radius } => {
+                chart.draw_series(points.iter().map(|&p| Circle::new(p, *radius, color.filled())))?
+            }
+            SeriesData::Line { points, max_gap } => chart.draw_series(
+                line_runs(points, *max_gap).into_iter().map(|run| PathElement::new(run, color.stroke_width(3))),
+            )?,
+            SeriesData::Histogram { values, bin_width, normalise } => chart.draw_series(
+                histogram_bins(values, *bin_width, *normalise)
+                    .into_iter()
+                    .map(|(start, height)| Rectangle::new([(start, 0.0), (start + bin_width, height)], color.mix(0.8).filled())),
+            )?,
+            SeriesData::Band { points } => chart.draw_series(std::iter::once(Polygon::new(
+                points.iter().map(|p| (p.0, p.2)).chain(points.iter().rev().map(|p| (p.0, p.1))).collect::<Vec<_>>(),
+                color.mix(0.3),
+            )))?,
+            // Whisker, then the box, then the median across it
+            SeriesData::Boxes { boxes, width } => {
+                let half = width / 2.0;
+                chart.draw_series(boxes.iter().map(|(x, q)| PathElement::new([(*x, q[0]), (*x, q[4])], color.stroke_width(3))))?;
+                chart.draw_series(boxes.iter().map(|(x, q)| Rectangle::new([(x - half, q[1]), (x + half, q[3])], color.filled())))?;
+                chart.draw_series(
+                    boxes.iter().map(|(x, q)| PathElement::new([(x - half, q[2]), (x + half, q[2])], background.stroke_width(4))),
+                )?
+            }
+            SeriesData::Violins { violins, width } => chart.draw_series(violins.iter().map(|(x, density)| {
+                let peak = density.iter().map(|d| d.1).fold(0.0, f64::max);
+                let half_width = |d: f64| if peak > 0.0 { d / peak * width / 2.0 } else { 0.0 };
+                let right = density.iter().map(|&(y, d)| (x + half_width(d), y));
+                let left = density.iter().rev().map(|&(y, d)| (x - half_width(d), y));
+                Polygon::new(right.chain(left).collect::<Vec<_>>(), color.mix(0.35))
+            }))?,
+            SeriesData::Bars { bars } => chart.draw_series(
+                bars.iter().map(|&(start, end, height, colour)| Rectangle::new([(start, 0.0), (end, height)], colour.filled())),
+            )?,
+            SeriesData::Cells { cells } => {
+                chart.draw_series(cells.iter().map(|&(x0, y0, x1, y1, colour)| Rectangle::new([(x0, y0), (x1, y1)], colour.filled())))?
+            }
+        };
+        if !series.name.is_empty() {
+            annotation
+                .label(series.name.as_str())
+                .legend(move |(x, y)| Rectangle::new([(x, y - 10), (x + 30, y + 10)], color.filled()));
+        }
+    }
+
+    if spec.legend {
+        draw_legend(&mut chart, theme, spec.legend_position.clone())?;
+    }
+
+    root.present()?;
+    Ok(())
+}
+
+fn draw_legend<'a, DB: DrawingBackend + 'a, CT: CoordTranslate>(
+    chart: &mut ChartContext<'a, DB, CT>,
+    theme: &Theme,
+    position: SeriesLabelPosition,
+) -> ChartResult
+where
+    DB::ErrorType: 'static,
+{
+    chart
+        .configure_series_labels()
+        .background_style(theme.background.mix(0.8))
+        .border_style(theme.foreground)
+        .label_font(("sans-serif", theme.font_size * 4 / 7, &theme.foreground))
+        .position(position)
+        .draw()?;
+    Ok(())
+}
+
+fn draw_colour_bar<DB: DrawingBackend>(area: &DrawingArea<DB, Shift>, colour_bar: &ColourBar, theme: &Theme) -> ChartResult
+where
+    DB::ErrorType: 'static,
+{
+    let foreground = theme.foreground;
+    let font_size = theme.font_size * 4 / 7;
+    let range = colour_bar.range.clone();
+    let mut chart = ChartBuilder::on(area)
+        .set_label_area_size(LabelAreaPosition::Left, font_size * 3)
+        .margin(theme.margin / 2)
+        .build_cartesian_2d(0.0..1.0, range.clone())?;
+    chart
+        .configure_mesh()
+        .disable_mesh()
+        .disable_x_axis()
+        .y_labels(9)
+        .y_label_formatter(&|v| format!("{:+.0}", v))
+        .y_desc(colour_bar.description.as_str())
+        .axis_desc_style(("sans-serif", font_size, &foreground))
+        .axis_style(foreground)
+        .label_style(("sans-serif", font_size, &foreground))
+        .draw()?;
+    const STEPS: usize = 100;
+    let step = (range.end - range.start) / STEPS as f64;
+    chart.draw_series((0..STEPS).map(|i| {
+        let low = range.start + i as f64 * step;
+        Rectangle::new([(0.0, low), (1.0, low + step)], colour_bar.map.colour((i as f64 + 0.5) / STEPS as f64).filled())
+    }))?;
+    Ok(())
+}
+
+// Wind rose (see wind::wind_rose) as a polar chart: one wedge per direction
+// sector, split outwards into speed classes, with rings marking the share of
+// the time
+pub struct WindRoseChart {
+    pub rose: WindRose,
+    pub title: String,
+    pub theme: Theme,
+}
+
+impl WindRoseChart {
+    pub fn new(rose: WindRose) -> WindRoseChart {
+        WindRoseChart { rose, title: "Wind rose".to_string(), theme: Theme::dark() }
+    }
+}
+
+// Point at `radius` in the direction `degrees` (clockwise from north)
+fn polar(radius: f64, degrees: f64) -> (f64, f64) {
+    let radians = degrees.to_radians();
+    (radius * radians.sin(), radius * radians.cos())
+}
+
+// Points along an arc, one every couple of degrees
+fn arc(radius: f64, from: f64, to: f64) -> impl DoubleEndedIterator<Item = (f64, f64)> + Clone {
+    let steps = ((to - from) / 2.0).ceil().max(1.0) as usize;
+    (0..=steps).map(move |i| polar(radius, from + (to - from) * i as f64 / steps as f64))
+}
+
+// Spacing of the percentage rings: 1, 2 or 5 times a power of ten, giving
+// three to six rings
+fn ring_step(largest: f64) -> f64 {
+    let magnitude = 10f64.powf((largest / 4.0).log10().floor());
+    [1.0, 2.0, 5.0, 10.0].iter().map(|m| m * magnitude).find(|step| largest / step <= 6.0).unwrap_or(10.0 * magnitude)
+}
+
+impl Chart for WindRoseChart {
+    fn size(&self) -> (u32, u32) {
+        (1200, 1200)
+    }
+
+    fn draw<DB: DrawingBackend>(&self, root: &DrawingArea<DB, Shift>) -> ChartResult
+    where
+        DB::ErrorType: 'static,
+    {
+        let theme = &self.theme;
+        let foreground = theme.foreground;
+        let font_size = theme.font_size * 4 / 7;
+        root.fill(&theme.background)?;
+
+        let largest = self.rose.frequencies.iter().map(|c| c.iter().sum::<f64>()).fold(0.0, f64::max).max(1.0);
+        let step = ring_step(largest);
+        let outer = (largest / step).ceil() * step;
+        let extent = outer * 1.15;
+        let mut chart = ChartBuilder::on(root)
+            .margin(theme.margin)
+            .caption(&self.title, ("sans-serif", font_size, &foreground))
+            .build_cartesian_2d(-extent..extent, -extent..extent)?;
+
+        // Rings and spokes every 45° under the wedges, the ring percentages
+        // over them
+        let grid = foreground.mix(0.3);
+        let rings: Vec<f64> = (1..).map(|i| i as f64 * step).take_while(|ring| *ring <= outer + 1e-9).collect();
+        chart.draw_series(rings.iter().map(|&ring| PathElement::new(arc(ring, 0.0, 360.0).collect::<Vec<_>>(), grid)))?;
+        chart.draw_series((0..8).map(|i| PathElement::new(vec![(0.0, 0.0), polar(outer, i as f64 * 45.0)], grid)))?;
+        let centred = ("sans-serif", font_size).into_font().color(&foreground).pos(Pos::new(HPos::Center, VPos::Center));
+        chart.draw_series(
+            ["N", "E", "S", "W"].iter().enumerate().map(|(i, name)| Text::new(*name, polar(outer * 1.08, i as f64 * 90.0), centred.clone())),
+        )?;
+
+        // One wedge per sector, stacked outwards from the slowest class
+        let width = 360.0 / self.rose.sectors() as f64 * 0.9;
+        let classes = self.rose.classes.len() + 1;
+        let colour = |class: usize| PALETTE[class % PALETTE.len()];
+        for (sector, shares) in self.rose.frequencies.iter().enumerate() {
+            let centre = sector as f64 * 360.0 / self.rose.sectors() as f64;
+            let (from, to) = (centre - width / 2.0, centre + width / 2.0);
+            let mut inner = 0.0;
+            for (class, share) in shares.iter().enumerate() {
+                if *share <= 0.0 {
+                    continue;
+                }
+                let points: Vec<(f64, f64)> = arc(inner + share, from, to).chain(arc(inner, from, to).rev()).collect();
+                chart.draw_series(std::iter::once(Polygon::new(points, colour(class).filled())))?;
+                inner += share;
+            }
+        }
+        chart.draw_series(rings.iter().map(|&ring| {
+            Text::new(format!("{}%", ring), polar(ring, 22.5), ("sans-serif", font_size * 2 / 3).into_font().color(&foreground.mix(0.8)))
+        }))?;
+        for class in 0..classes {
+            let fill = colour(class);
+            chart
+                .draw_series(std::iter::empty::<Rectangle<(f64, f64)>>())?
+                .label(format!("{} m/s", wind::class_name(&self.rose, class)))
+                .legend(move |(x, y)| Rectangle::new([(x, y - 10), (x + 30, y + 10)], fill.filled()));
+        }
+        draw_legend(&mut chart, theme, SeriesLabelPosition::UpperRight)?;
+        chart.draw_series(std::iter::once(Text::new(
+            format!("Calm {:.1}%", self.rose.calm),
+            (-extent * 0.95, -extent * 0.95),
+            ("sans-serif", font_size * 2 / 3).into_font().color(&foreground).pos(Pos::new(HPos::Left, VPos::Bottom)),
+        )))?;
+
+        root.present()?;
+        Ok(())
+    }
+}
+
+// Writes the chart to `path`, as SVG if it ends in .svg and PNG otherwise
+pub fn to_file<C: Chart>(chart: &C, path: &str) -> ChartResult {
+    match Format::from_path(path) {
+        Format::Png => chart.draw(&BitMapBackend::new(path, chart.size()).into_drawing_area()),
+        Format::Svg => chart.draw(&SVGBackend::new(path, chart.size()).into_drawing_area()),
+    }
+}
+
+// Animated GIF with one frame per chart, all drawn at the size of the first.
+// Every chart's draw() ends with present(), which is what writes a frame.
+pub fn to_gif<C: Chart>(frames: &[C], path: &str, frames_per_second: u32) -> ChartResult {
+    let Some(first) = frames.first() else { return Ok(()) };
+    let delay = 1000 / frames_per_second.max(1);
+    let root = BitMapBackend::gif(path, first.size(), delay)?.into_drawing_area();
+    for frame in frames {
+        frame.draw(&root)?;
+    }
+    Ok(())
+}
+
+pub fn to_svg<C: Chart>(chart: &C) -> Result<String, Box<dyn std::error::Error>> {
+    let mut svg = String::new();
+    {
+        let root = SVGBackend::with_string(&mut svg, chart.size()).into_drawing_area();
+        chart.draw(&root)?;
+    }
+    Ok(svg)
+}
+
+// PNG file contents, encoded in memory
+pub fn to_png<C: Chart>(chart: &C) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let (width, height) = chart.size();
+    let mut pixels = vec![0; width as usize * height as usize * 3];
+    {
+        let root = BitMapBackend::with_buffer(&mut pixels, (width, height)).into_drawing_area();
+        chart.draw(&root)?;
+    }
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(&pixels, width, height, ColorType::Rgb8)?;
+    Ok(png)
+}
+
+pub fn to_bytes<C: Chart>(chart: &C, format: Format) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    match format {
+        Format::Png => to_png(chart),
+        Format::Svg => Ok(to_svg(chart)?.into_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Tenths;
+
+    #[test]
+    fn lines_break_at_gaps() {
+        let points = [(0.0, 1.0), (60.0, 2.0), (120.0, 3.0), (600.0, 4.0), (660.0, 5.0)];
+        assert_eq!(line_runs(&points, None).len(), 1);
+        let runs = line_runs(&points, Some(180.0));
+        assert_eq!(runs, vec![points[..3].to_vec(), points[3..].to_vec()]);
+        assert!(line_runs(&[], Some(1.0)).is_empty());
+    }
+
+    #[test]
+    fn distribution_axes_fit_the_data() {
+        // Three hours at 2.3 °C and one at 7.8 °C: the tallest bin holds 3/4
+        let temps: Vec<TempData> = [(2.3, 180), (7.8, 60)]
+            .iter()
+            .map(|&(degrees, duration)| TempData { temp10: Tenths::from_degrees(degrees), duration, minute_of_year: 0 })
+            .collect();
+        let spec = ChartSpec::distribution(&temps);
+        assert_eq!(spec.x.range, 1.0..9.0);
+        assert!((spec.y.range.end - 0.75 * 1.1).abs() < 1e-9);
+        assert_eq!(ChartSpec::distribution(&[]).x.range, -15.0..15.0);
+    }
+}
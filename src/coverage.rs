@@ -0,0 +1,175 @@
+use plotters::style::RGBColor;
+
+use crate::chart::{self, ChartResult, ChartSpec};
+use crate::daily;
+use crate::TempData;
+
+/*
+Data completeness. A strange result is only interesting if the data behind it
+is good, so for every station and year this reports how many observations
+there were per day, how many minutes they cover, the longest gap, how many
+records quality control dropped and why, and the mix of report types (METAR,
+SYNOP, specials, ...).
+*/
+
+// How long a single observation is considered to cover. Durations in TempData
+// run until the next observation, so without a cap a twelve hour outage would
+// count as covered.
+const MAX_COVERED_MINUTES: u32 = 90;
+
+pub struct YearCoverage {
+    pub year: u32,
+    pub total_records: usize,
+    pub valid_records: usize,
+    pub observations_per_day: Vec<u32>, // Indexed by day of year
+    pub minutes_per_day: Vec<u32>, // Minutes covered, indexed by day of year
+    pub longest_gap: Option<(u32, u32)>, // (minute of year it started, length in minutes)
+    pub days_below_threshold: usize,
+    pub dropped: Vec<(String, usize)>, // Reason, number of records. Most common first
+    pub report_types: Vec<(String, usize)>, // Most common first
+}
+
+impl YearCoverage {
+    pub fn days_in_year(&self) -> usize {
+        if daily::is_leap_year(self.year) {
+            366
+        } else {
+            365
+        }
+    }
+
+    pub fn coverage_fraction(&self) -> f64 {
+        self.minutes_per_day.iter().map(|m| *m as f64).sum::<f64>() / (self.days_in_year() as f64 * 1440.0)
+    }
+}
+
+fn count_by<I: Iterator<Item = String>>(items: I) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for item in items {
+        match counts.iter_mut().find(|(name, _)| *name == item) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((item, 1)),
+        }
+    }
+    counts.sort_by_key(|c| std::cmp::Reverse(c.1));
+    counts
+}
+
+// Builds the report for one year of raw records (before remove_invalid_entries).
+// Days covering less than `threshold` (0..1) of their minutes are counted as
+// below the threshold.
+pub fn year_coverage(raw_data: &[String], year: u32, threshold: f64) -> YearCoverage {
+    let mut valid: Vec<String> = raw_data.to_vec();
+    crate::remove_invalid_entries(&mut valid);
+    let temps: Vec<TempData> = crate::extract_detailed_temps(&valid);
+
+    let mut observations_per_day = vec![0; 366];
+    let mut minutes_per_day = vec![0; 366];
+    for temp in &temps {
+        observations_per_day[crate::get_day_index_from_minutes(temp.minute_of_year)] += 1;
+        // Split the covered minutes across midnight
+        let covered_end = temp.minute_of_year + (temp.duration as u32).min(MAX_COVERED_MINUTES);
+        let mut minute = temp.minute_of_year;
+        while minute < covered_end {
+            let day = crate::get_day_index_from_minutes(minute);
+            let until = ((day as u32 + 1) * 1440).min(covered_end);
+            if day < 366 {
+                minutes_per_day[day] += until - minute;
+            }
+            minute = until;
+        }
+    }
+    let days_in_year = if daily::is_leap_year(year) { 366 } else { 365 };
+    let days_below_threshold = minutes_per_day[..days_in_year]
+        .iter()
+        .filter(|&&m| (m as f64) < threshold * 1440.0)
+        .count();
+
+    // Gaps before the first and after the last observation count too
+    let mut gaps: Vec<(u32, u32)> = temps.iter().map(|t| (t.minute_of_year, t.duration as u32)).collect();
+    if let Some(first) = temps.first() {
+        gaps.push((0, first.minute_of_year));
+    }
+    if let Some(last) = temps.last() {
+        let end = last.minute_of_year + last.duration as u32;
+        gaps.push((end, (days_in_year as u32 * 1440).saturating_sub(end)));
+    }
+    let longest_gap = gaps.into_iter().max_by_key(|g| g.1);
+
+    YearCoverage {
+        year,
+        total_records: raw_data.len(),
+        valid_records: temps.len(),
+        observations_per_day,
+        minutes_per_day,
+        longest_gap,
+        days_below_threshold,
+        dropped: count_by(raw_data.iter().filter_map(|line| crate::rejection_reason(line))),
+        report_types: count_by(raw_data.iter().filter_map(|line| Some(line.get(41..46)?.trim().to_string()))),
+    }
+}
+
+pub fn print_coverage_table(station: &str, years: &[YearCoverage]) {
+    println!("Data coverage at {}:", station);
+    println!(
+        "{:>6} {:>8} {:>8} {:>9} {:>9} {:>10} {:>17} {:>10}",
+        "Year", "Records", "Valid", "Obs/day", "Covered", "Gap (h)", "Gap start", "Days <thr"
+    );
+    for year in years {
+        let observations: u32 = year.observations_per_day.iter().sum();
+        let (gap_hours, gap_start) = match year.longest_gap {
+            Some((start, length)) => {
+                let day = crate::get_day_index_from_minutes(start);
+                let minute = start % 1440;
+                (
+                    format!("{:.1}", length as f64 / 60.0),
+                    format!("{} {:02}:{:02}", daily::format_date(year.year, day), minute / 60, minute % 60),
+                )
+            }
+            None => ("-".to_string(), "-".to_string()),
+        };
+        println!(
+            "{:>6} {:>8} {:>8} {:>9.1} {:>8.1}% {:>10} {:>17} {:>10}",
+            year.year,
+            year.total_records,
+            year.valid_records,
+            observations as f64 / year.days_in_year() as f64,
+            year.coverage_fraction() * 100.0,
+            gap_hours,
+            gap_start,
+            year.days_below_threshold
+        );
+    }
+    for year in years {
+        let dropped: Vec<String> = year.dropped.iter().map(|(reason, n)| format!("{}: {}", reason, n)).collect();
+        let types: Vec<String> = year.report_types.iter().map(|(kind, n)| format!("{}: {}", kind, n)).collect();
+        println!("  {} dropped by QC: {}", year.year, if dropped.is_empty() { "none".to_string() } else { dropped.join(", ") });
+        println!("  {} report types: {}", year.year, types.join(", "));
+    }
+}
+
+// Colour for a fraction of the day covered, red (nothing) through yellow to
+// green (everything)
+fn coverage_colour(fraction: f64) -> RGBColor {
+    let f = fraction.clamp(0.0, 1.0);
+    if f < 0.5 {
+        RGBColor(200, (f * 2.0 * 200.0) as u8, 40)
+    } else {
+        RGBColor(((1.0 - f) * 2.0 * 200.0) as u8, 200, 40)
+    }
+}
+
+// GitHub style calendar of the fraction of each day covered: one column per
+// week, one row per day of the week.
+pub fn coverage_calendar(coverage: &YearCoverage) -> ChartSpec {
+    let colours: Vec<Option<RGBColor>> =
+        coverage.minutes_per_day.iter().map(|&minutes| Some(coverage_colour(minutes as f64 / 1440.0))).collect();
+    let mut spec = ChartSpec::calendar(coverage.year, &colours);
+    spec.title = Some(format!("Data coverage in {}", coverage.year));
+    spec
+}
+
+// Writes the calendar to `path`, as SVG if it ends in .svg and PNG otherwise
+pub fn plot_coverage_calendar(coverage: &YearCoverage, path: &str) -> ChartResult {
+    chart::to_file(&coverage_calendar(coverage), path)
+}
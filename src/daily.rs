@@ -0,0 +1,121 @@
+use crate::TempData;
+
+// Summary of a single day of observations. Temperatures are in °C.
+#[derive(Clone, Copy, Debug)]
+pub struct DailyStats {
+    pub max: f64,
+    pub min: f64,
+    pub mean: f64, // Weighted by the duration of each observation
+    pub minutes: u32, // Minutes of the day covered by observations
+}
+
+// Which daily aggregate an analysis should work on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DailyVariable {
+    Max,
+    Min,
+    Mean,
+}
+
+impl DailyStats {
+    pub fn get(&self, variable: DailyVariable) -> f64 {
+        match variable {
+            DailyVariable::Max => self.max,
+            DailyVariable::Min => self.min,
+            DailyVariable::Mean => self.mean,
+        }
+    }
+}
+
+// Aggregates a year of observations into days. The result always has 366
+// entries indexed by day of year, with None for days without any data.
+pub fn daily_stats(temps: &[TempData]) -> Vec<Option<DailyStats>> {
+    let mut max = [i16::MIN; 366];
+    let mut min = [i16::MAX; 366];
+    let mut first_moment = [0i64; 366];
+    let mut minutes = [0u32; 366];
+    for temp in temps {
+        let day = crate::get_day_index_from_minutes(temp.minute_of_year);
+        max[day] = max[day].max(temp.temp10.tenths());
+        min[day] = min[day].min(temp.temp10.tenths());
+        first_moment[day] += temp.temp10.tenths() as i64 * temp.duration as i64;
+        minutes[day] += temp.duration as u32;
+    }
+    (0..366)
+        .map(|day| {
+            if max[day] == i16::MIN {
+                return None;
+            }
+            // Observations with a duration of zero still count towards the
+            // extremes, but cannot give a weighted mean.
+            let mean = if minutes[day] > 0 {
+                first_moment[day] as f64 / (minutes[day] as f64 * 10.0)
+            } else {
+                (max[day] as f64 + min[day] as f64) / 20.0
+            };
+            Some(DailyStats {
+                max: max[day] as f64 / 10.0,
+                min: min[day] as f64 / 10.0,
+                mean,
+                minutes: minutes[day],
+            })
+        })
+        .collect()
+}
+
+// Day index on the leap year calendar, so that March 1st is always 60 and
+// every date lines up across years
+pub fn calendar_day(year: u32, day: usize) -> usize {
+    if !is_leap_year(year) && day >= 59 {
+        day + 1
+    } else {
+        day
+    }
+}
+
+// daily_stats() of a year indexed by calendar_day(), so 29 February is None
+// outside leap years
+pub fn calendar_daily_stats(temps: &[TempData], year: u32) -> Vec<Option<DailyStats>> {
+    let days_in_year = if is_leap_year(year) { 366 } else { 365 };
+    let mut days = vec![None; 366];
+    for (day, stats) in daily_stats(temps).into_iter().enumerate().take(days_in_year) {
+        days[calendar_day(year, day)] = stats;
+    }
+    days
+}
+
+pub const MONTH_NAMES: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+pub fn is_leap_year(year: u32) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+// Converts a zero based day index back into a (month, day) pair, both starting
+// at one.
+pub fn month_and_day(year: u32, day_index: usize) -> (usize, usize) {
+    let mut month_lengths = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    if is_leap_year(year) {
+        month_lengths[1] = 29;
+    }
+    let mut remaining = day_index;
+    for (month, &length) in month_lengths.iter().enumerate() {
+        if remaining < length {
+            return (month + 1, remaining + 1);
+        }
+        remaining -= length;
+    }
+    (12, 31)
+}
+
+// Formats a day index as YYYY-MM-DD
+pub fn format_date(year: u32, day_index: usize) -> String {
+    let (month, day) = month_and_day(year, day_index);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Day of the week, 0 for Sunday (Sakamoto's method)
+pub fn day_of_week(year: u32, month: usize, day: usize) -> usize {
+    const OFFSETS: [usize; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+    let year = if month < 3 { year - 1 } else { year } as usize;
+    (year + year / 4 - year / 100 + year / 400 + OFFSETS[month - 1] + day) % 7
+}
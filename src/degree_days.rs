@@ -0,0 +1,217 @@
+use crate::daily;
+use crate::units;
+use crate::TempData;
+
+/*
+Heating and cooling degree days. For every day, heating degree days (HDD) are
+how far the temperature was below a base temperature and cooling degree days
+(CDD) are how far it was above it. Two methods are supported:
+    - DailyAverage: the classic (max + min) / 2 for the day, compared to the
+      base once.
+    - Integrated: every observation is compared to the base and weighted by how
+      long it lasted. This catches days where it was cold in the morning and
+      hot in the afternoon, which the daily average method nets out to zero.
+All values are in °C·days, and converted to the chosen unit when printed.
+*/
+
+// 65 °F, the base temperature used by the NWS
+pub const DEFAULT_BASE: f64 = 18.3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DegreeDayMethod {
+    DailyAverage,
+    Integrated,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DegreeDays {
+    pub heating: f64,
+    pub cooling: f64,
+    pub days: f64, // Days of data behind the totals
+}
+
+// One entry per calendar month, January first
+pub type MonthlyDegreeDays = [DegreeDays; 12];
+
+impl std::ops::AddAssign for DegreeDays {
+    fn add_assign(&mut self, other: Self) {
+        self.heating += other.heating;
+        self.cooling += other.cooling;
+        self.days += other.days;
+    }
+}
+
+// Degree days for every day of the year (366 entries, None for days without
+// data).
+pub fn daily_degree_days(temps: &[TempData], base: f64, method: DegreeDayMethod) -> Vec<Option<DegreeDays>> {
+    match method {
+        DegreeDayMethod::DailyAverage => daily::daily_stats(temps)
+            .iter()
+            .map(|day| {
+                day.map(|d| {
+                    let average = (d.max + d.min) / 2.0;
+                    DegreeDays { heating: (base - average).max(0.0), cooling: (average - base).max(0.0), days: 1.0 }
+                })
+            })
+            .collect(),
+        DegreeDayMethod::Integrated => {
+            let mut days: Vec<Option<DegreeDays>> = vec![None; 366];
+            for temp in temps {
+                let t = temp.temp10.degrees();
+                let fraction_of_day = temp.duration as f64 / 1440.0;
+                let day = days[crate::get_day_index_from_minutes(temp.minute_of_year)].get_or_insert_with(DegreeDays::default);
+                day.heating += (base - t).max(0.0) * fraction_of_day;
+                day.cooling += (t - base).max(0.0) * fraction_of_day;
+                day.days += fraction_of_day;
+            }
+            days
+        }
+    }
+}
+
+// Sums the daily values of `year` into calendar months
+pub fn monthly_totals(year: u32, days: &[Option<DegreeDays>]) -> MonthlyDegreeDays {
+    let mut months: MonthlyDegreeDays = [DegreeDays::default(); 12];
+    for (day_index, day) in days.iter().enumerate() {
+        if let Some(day) = day {
+            let (month, _) = daily::month_and_day(year, day_index);
+            months[month - 1] += *day;
+        }
+    }
+    months
+}
+
+pub fn annual_total(months: &MonthlyDegreeDays) -> DegreeDays {
+    let mut total = DegreeDays::default();
+    for month in months {
+        total += *month;
+    }
+    total
+}
+
+// Monthly totals for every year in `years`, where `years[0]` is `first_year`
+pub fn monthly_totals_by_year(
+    years: &[Vec<TempData>],
+    first_year: u32,
+    base: f64,
+    method: DegreeDayMethod,
+) -> Vec<MonthlyDegreeDays> {
+    years
+        .iter()
+        .enumerate()
+        .map(|(i, temps)| monthly_totals(first_year + i as u32, &daily_degree_days(temps, base, method)))
+        .collect()
+}
+
+// Number of days in every month of `year`
+fn month_lengths(year: u32) -> [f64; 12] {
+    let mut lengths = [0.0; 12];
+    let days = if daily::is_leap_year(year) { 366 } else { 365 };
+    for day_index in 0..days {
+        lengths[daily::month_and_day(year, day_index).0 - 1] += 1.0;
+    }
+    lengths
+}
+
+// Average monthly totals over several years, where `monthly_by_year[0]` is
+// `first_year`. Years with gaps would drag the average down, so each month is
+// the degree days per day of data over all the years, times the average length
+// of the month.
+pub fn climatology(monthly_by_year: &[MonthlyDegreeDays], first_year: u32) -> MonthlyDegreeDays {
+    let mut totals: MonthlyDegreeDays = [DegreeDays::default(); 12];
+    let mut lengths = [0.0; 12];
+    for (i, year) in monthly_by_year.iter().enumerate() {
+        for (total, month) in totals.iter_mut().zip(year) {
+            *total += *month;
+        }
+        for (length, days) in lengths.iter_mut().zip(month_lengths(first_year + i as u32)) {
+            *length += days;
+        }
+    }
+    let count = monthly_by_year.len().max(1) as f64;
+    let mut normal: MonthlyDegreeDays = [DegreeDays::default(); 12];
+    for ((n, total), length) in normal.iter_mut().zip(&totals).zip(lengths) {
+        if total.days > 0.0 {
+            let days = length / count;
+            *n = DegreeDays { heating: total.heating / total.days * days, cooling: total.cooling / total.days * days, days };
+        }
+    }
+    normal
+}
+
+// Prints a table of monthly HDD/CDD with one column per station. If a
+// climatology is given for a station, the departure from it is shown too.
+// `base` is the base temperature the degree days were counted from, in °C.
+pub fn print_degree_days(stations: &[(&str, MonthlyDegreeDays, Option<MonthlyDegreeDays>)], base: f64) {
+    print!("{:<6}", "Month");
+    for (name, _, _) in stations {
+        print!("{:>32}", name);
+    }
+    println!();
+    let format_column = |actual: DegreeDays, normal: Option<DegreeDays>| match normal {
+        Some(n) => format!(
+            "{:.0} ({:+.0}) / {:.0} ({:+.0})",
+            units::difference(actual.heating),
+            units::difference(actual.heating - n.heating),
+            units::difference(actual.cooling),
+            units::difference(actual.cooling - n.cooling)
+        ),
+        None => format!("{:.0} / {:.0}", units::difference(actual.heating), units::difference(actual.cooling)),
+    };
+    for (month, month_name) in daily::MONTH_NAMES.iter().enumerate() {
+        print!("{:<6}", month_name);
+        for (_, actual, normal) in stations {
+            print!("{:>32}", format_column(actual[month], normal.map(|n| n[month])));
+        }
+        println!();
+    }
+    print!("{:<6}", "Year");
+    for (_, actual, normal) in stations {
+        print!("{:>32}", format_column(annual_total(actual), normal.map(|n| annual_total(&n))));
+    }
+    println!();
+    println!(
+        "(HDD / CDD in {}·days from a base of {:.1} {}, departure from normal in brackets)",
+        units::symbol(),
+        units::temperature(base),
+        units::symbol()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Tenths;
+
+    // Hourly observations at a constant temperature from `start` to `end`,
+    // in days of the year
+    fn constant(degrees: f64, start: u32, end: u32) -> Vec<TempData> {
+        (start * 24..end * 24)
+            .map(|hour| TempData { temp10: Tenths::from_degrees(degrees), duration: 60, minute_of_year: hour * 60 })
+            .collect()
+    }
+
+    #[test]
+    fn constant_temperature_gives_the_difference_from_the_base_every_day() {
+        let temps = constant(10.0, 0, 365);
+        for method in [DegreeDayMethod::Integrated, DegreeDayMethod::DailyAverage] {
+            let months = monthly_totals(2022, &daily_degree_days(&temps, DEFAULT_BASE, method));
+            assert!((months[0].heating - (DEFAULT_BASE - 10.0) * 31.0).abs() < 1e-9);
+            assert!((months[1].heating - (DEFAULT_BASE - 10.0) * 28.0).abs() < 1e-9);
+            assert_eq!(months[0].cooling, 0.0);
+            assert!((months[0].days - 31.0).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn climatology_only_counts_the_days_with_data() {
+        // 2021 is complete, 2022 only has the first half of January
+        let years = [constant(10.0, 0, 365), constant(10.0, 0, 15)];
+        let by_year = monthly_totals_by_year(&years, 2021, DEFAULT_BASE, DegreeDayMethod::Integrated);
+        let normal = climatology(&by_year, 2021);
+        assert!((normal[0].heating - (DEFAULT_BASE - 10.0) * 31.0).abs() < 1e-9);
+        assert!((normal[1].heating - (DEFAULT_BASE - 10.0) * 28.0).abs() < 1e-9);
+        // Months without data in any year have no normal
+        assert_eq!(climatology(&by_year[1..], 2022)[1].days, 0.0);
+    }
+}
@@ -0,0 +1,143 @@
+use plotters::prelude::*;
+
+use crate::chart::{Axis, ChartSpec, Grid, Series, SeriesData, Theme, PALETTE};
+use crate::daily;
+use crate::stats::{self, Bandwidth, Kernel};
+use crate::units;
+use crate::TempData;
+
+/*
+Monthly temperature distributions for comparing the seasonal spread of
+stations. Every reading is weighted by its duration, the same as the
+histograms, so a station which reports more often in the afternoon doesn't
+look warmer. Each month gets a box plot (weighted quartiles, with whiskers at
+the furthest readings within 1.5 IQR of the box) drawn inside a violin of
+the kernel density estimate.
+*/
+
+#[derive(Clone, Copy, Debug)]
+pub struct BoxStats {
+    pub lower_whisker: f64, // °C
+    pub lower_quartile: f64,
+    pub median: f64,
+    pub upper_quartile: f64,
+    pub upper_whisker: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct MonthDistribution {
+    pub quartiles: BoxStats,
+    pub density: Vec<(f64, f64)>, // (°C, density per °C), ascending
+}
+
+pub fn box_stats(values: &[(f64, f64)]) -> Option<BoxStats> {
+    let minutes: f64 = values.iter().map(|v| v.1).sum();
+    if minutes <= 0.0 {
+        return None;
+    }
+    let lower_quartile = stats::weighted_quantile(values, 0.25);
+    let upper_quartile = stats::weighted_quantile(values, 0.75);
+    let reach = 1.5 * (upper_quartile - lower_quartile);
+    let inside = values.iter().map(|v| v.0).filter(|v| (lower_quartile - reach..=upper_quartile + reach).contains(v));
+    let (lower_whisker, upper_whisker) =
+        inside.fold((lower_quartile, upper_quartile), |(low, high), v| (low.min(v), high.max(v)));
+    Some(BoxStats {
+        lower_whisker,
+        lower_quartile,
+        median: stats::weighted_quantile(values, 0.5),
+        upper_quartile,
+        upper_whisker,
+    })
+}
+
+// Distribution of every calendar month over all the years, None for months
+// without data. `years[0]` is `first_year`. The violins are a kernel density
+// estimate with the given kernel and bandwidth rule.
+pub fn monthly_distributions(years: &[Vec<TempData>], first_year: u32, kernel: Kernel, rule: Bandwidth) -> Vec<Option<MonthDistribution>> {
+    let mut months: Vec<Vec<(f64, f64)>> = vec![Vec::new(); 12];
+    for (temps, year) in years.iter().zip(first_year..) {
+        for temp in temps {
+            let (month, _) = daily::month_and_day(year, crate::get_day_index_from_minutes(temp.minute_of_year));
+            months[month - 1].push((temp.temp10.degrees(), temp.duration as f64));
+        }
+    }
+    months
+        .iter()
+        .map(|values| {
+            let quartiles = box_stats(values)?;
+            Some(MonthDistribution { quartiles, density: stats::kde_curve(values, kernel, rule, 200) })
+        })
+        .collect()
+}
+
+pub struct MonthlyBoxViolin {
+    pub stations: Vec<(String, Vec<Option<MonthDistribution>>)>, // Twelve months per station
+    pub title: String,
+    pub theme: Theme,
+}
+
+fn month_label(x: &f64) -> String {
+    let month = x.round();
+    if (x - month).abs() < 1e-6 && (0.0..12.0).contains(&month) {
+        daily::MONTH_NAMES[month as usize].to_string()
+    } else {
+        String::new()
+    }
+}
+
+impl MonthlyBoxViolin {
+    pub fn new(stations: Vec<(String, Vec<Option<MonthDistribution>>)>) -> MonthlyBoxViolin {
+        MonthlyBoxViolin { stations, title: "Monthly temperature distribution".to_string(), theme: Theme::dark() }
+    }
+
+    // Lowest and highest temperature with any density, °C
+    fn range(&self) -> (f64, f64) {
+        let values = self.stations.iter().flat_map(|(_, months)| months.iter().flatten()).flat_map(|m| m.density.iter().map(|d| d.0));
+        let (low, high) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), v| (low.min(v), high.max(v)));
+        if low.is_finite() {
+            (low, high)
+        } else {
+            (0.0, 1.0)
+        }
+    }
+
+    pub fn spec(&self) -> ChartSpec {
+        let (low, high) = self.range();
+        let (low, high) = (units::temperature(low), units::temperature(high));
+        let padding = (high - low) * 0.03;
+        let x = Axis { range: -0.5..11.5, description: "Month".to_string(), labels: 12, formatter: month_label };
+        let y = Axis {
+            range: low - padding..high + padding,
+            description: format!("Temperature ({})", units::symbol()),
+            labels: 12,
+            formatter: |v| format!("{:.0}", v),
+        };
+        let mut spec = ChartSpec::new(x, y);
+        spec.title = Some(self.title.clone());
+        spec.theme = self.theme;
+        spec.grid = Grid::Horizontal;
+
+        // Each month gets 0.8 of its slot, shared between the stations
+        let width = 0.8 / self.stations.len().max(1) as f64;
+        for (index, ((name, months), colour)) in self.stations.iter().zip(PALETTE.iter().cycle()).enumerate() {
+            let centre = |month: usize| month as f64 - 0.4 + width * (index as f64 + 0.5);
+            let present = || months.iter().enumerate().filter_map(|(month, d)| Some((centre(month), d.as_ref()?)));
+            let violins = present()
+                .map(|(centre, d)| (centre, d.density.iter().map(|&(v, density)| (units::temperature(v), density)).collect()))
+                .collect();
+            let boxes = present()
+                .map(|(centre, d)| {
+                    let q = d.quartiles;
+                    let quartiles = [q.lower_whisker, q.lower_quartile, q.median, q.upper_quartile, q.upper_whisker];
+                    (centre, quartiles.map(units::temperature))
+                })
+                .collect();
+            spec.series.push(Series { name: String::new(), color: *colour, data: SeriesData::Violins { violins, width: width * 0.9 } });
+            spec.series.push(Series { name: name.clone(), color: *colour, data: SeriesData::Boxes { boxes, width: width * 0.24 } });
+        }
+        spec.legend = self.stations.len() > 1;
+        spec.legend_position = SeriesLabelPosition::UpperLeft;
+        spec
+    }
+}
+
@@ -0,0 +1,278 @@
+use crate::daily;
+use crate::units;
+use crate::TempData;
+
+/*
+Hour of day climatology. Observations are binned by local hour and day of year,
+giving each station a 24 x 366 grid of means and spreads. From that we get the
+typical diurnal cycle for each month and can find days whose shape was
+abnormal, eg. a cold front arriving at noon so the afternoon is colder than the
+morning.
+
+Local time is standard time (no daylight saving), given as an offset from UTC
+in minutes. Day indices after February are off by one between leap and
+non-leap years; normals.rs and heatmap.rs line them up with
+daily::calendar_day() instead.
+*/
+
+// Weighted moments for a single bin. Weights are minutes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Moments {
+    pub weight: f64,
+    pub first: f64,
+    pub second: f64,
+}
+
+impl Moments {
+    fn add(&mut self, value: f64, weight: f64) {
+        self.weight += weight;
+        self.first += value * weight;
+        self.second += value * value * weight;
+    }
+
+    fn merge(&mut self, other: &Moments) {
+        self.weight += other.weight;
+        self.first += other.first;
+        self.second += other.second;
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.weight > 0.0 {
+            Some(self.first / self.weight)
+        } else {
+            None
+        }
+    }
+
+    pub fn standard_deviation(&self) -> Option<f64> {
+        let mean = self.mean()?;
+        Some((self.second / self.weight - mean * mean).max(0.0).sqrt())
+    }
+}
+
+pub struct HourlyClimatology {
+    pub bins: Vec<[Moments; 24]>, // Indexed by [day of year][local hour]
+    // Same as `bins`, but each hourly value has had its day's mean removed
+    // first. This is the spread of the shape of the day, without the day to
+    // day changes in level.
+    pub shape_bins: Vec<[Moments; 24]>,
+    pub utc_offset: i32, // minutes
+}
+
+// Calls `f(day, hour, minutes)` for every local hour that the observation
+// covers, splitting its duration at hour boundaries.
+fn for_each_local_hour<F: FnMut(usize, usize, u32)>(temp: &TempData, utc_offset: i32, mut f: F) {
+    let start = temp.minute_of_year as i64 + utc_offset as i64;
+    let end = start + temp.duration as i64;
+    let mut minute = start;
+    while minute < end {
+        let next_hour = (minute.div_euclid(60) + 1) * 60;
+        let until = next_hour.min(end);
+        if (0..366 * 1440).contains(&minute) {
+            let day = (minute / 1440) as usize;
+            let hour = ((minute % 1440) / 60) as usize;
+            f(day, hour, (until - minute) as u32);
+        }
+        minute = until;
+    }
+}
+
+// Builds the climatology from every year of data given for the station
+pub fn hourly_climatology(years: &[Vec<TempData>], utc_offset: i32) -> HourlyClimatology {
+    let mut bins = vec![[Moments::default(); 24]; 366];
+    let mut shape_bins = vec![[Moments::default(); 24]; 366];
+    for temps in years {
+        for temp in temps {
+            let value = temp.temp10.degrees();
+            for_each_local_hour(temp, utc_offset, |day, hour, minutes| {
+                bins[day][hour].add(value, minutes as f64);
+            });
+        }
+        for (day, profile) in hourly_profiles(temps, utc_offset).iter().enumerate() {
+            let Some(day_mean) = complete_day_mean(profile) else {
+                continue;
+            };
+            for (hour, value) in profile.iter().enumerate() {
+                if let Some(value) = value {
+                    shape_bins[day][hour].add(value - day_mean, 1.0);
+                }
+            }
+        }
+    }
+    HourlyClimatology { bins, shape_bins, utc_offset }
+}
+
+// Mean of the hours in a profile, if enough of them have data
+fn complete_day_mean(profile: &[Option<f64>; 24]) -> Option<f64> {
+    let values: Vec<f64> = profile.iter().flatten().copied().collect();
+    if values.len() < MIN_HOURS_FOR_SHAPE {
+        return None;
+    }
+    Some(values.iter().sum::<f64>() / values.len() as f64)
+}
+
+// Pools the moments of one hour over ±`window` days
+fn pool(bins: &[[Moments; 24]], day: usize, hour: usize, window: usize) -> Moments {
+    let mut pooled = Moments::default();
+    for offset in 0..=(2 * window) {
+        let other = (day + 366 + offset - window) % 366;
+        pooled.merge(&bins[other][hour]);
+    }
+    pooled
+}
+
+impl HourlyClimatology {
+    // Moments for one hour of one day, pooled over ±`window` days to smooth
+    // out the noise from only having a handful of years.
+    pub fn smoothed(&self, day: usize, hour: usize, window: usize) -> Moments {
+        pool(&self.bins, day, hour, window)
+    }
+
+    // Same as `smoothed`, for the shape of the day
+    pub fn smoothed_shape(&self, day: usize, hour: usize, window: usize) -> Moments {
+        pool(&self.shape_bins, day, hour, window)
+    }
+
+    // Typical diurnal cycle for each month: (mean, standard deviation) for
+    // every local hour. Months use the leap year calendar.
+    pub fn monthly_cycles(&self) -> [[(f64, f64); 24]; 12] {
+        let mut months = [[Moments::default(); 24]; 12];
+        for (day, hours) in self.bins.iter().enumerate() {
+            let (month, _) = daily::month_and_day(2000, day);
+            for (m, h) in months[month - 1].iter_mut().zip(hours) {
+                m.merge(h);
+            }
+        }
+        months.map(|hours| {
+            hours.map(|h| (h.mean().unwrap_or(f64::NAN), h.standard_deviation().unwrap_or(f64::NAN)))
+        })
+    }
+}
+
+// Mean temperature for every local hour of every day of a single year
+pub fn hourly_profiles(temps: &[TempData], utc_offset: i32) -> Vec<[Option<f64>; 24]> {
+    let mut bins = vec![[Moments::default(); 24]; 366];
+    for temp in temps {
+        let value = temp.temp10.degrees();
+        for_each_local_hour(temp, utc_offset, |day, hour, minutes| {
+            bins[day][hour].add(value, minutes as f64);
+        });
+    }
+    bins.iter().map(|hours| hours.map(|h| h.mean())).collect()
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AbnormalDay {
+    pub day: usize,
+    // Root mean square of the hourly z-scores of the day's shape (its
+    // temperatures relative to its own mean) against the typical shape
+    pub score: f64,
+    pub worst_hour: usize, // Local hour with the largest departure from the usual shape
+    pub worst_departure: f64, // °C, negative means colder than the usual shape
+}
+
+// Hours of data a day needs before its shape is judged
+const MIN_HOURS_FOR_SHAPE: usize = 18;
+
+// Finds the days in `temps` whose diurnal shape differs from the climatology
+// by more than `threshold` (an RMS z-score, 2 is a good start). Comparing
+// shapes rather than values means a uniformly hot day is not abnormal, but a
+// day which peaks at 9am and falls all afternoon is.
+pub fn abnormal_days(
+    temps: &[TempData],
+    climatology: &HourlyClimatology,
+    window: usize,
+    threshold: f64,
+) -> Vec<AbnormalDay> {
+    let mut abnormal = Vec::new();
+    for (day, profile) in hourly_profiles(temps, climatology.utc_offset).iter().enumerate() {
+        let Some(day_mean) = complete_day_mean(profile) else {
+            continue;
+        };
+        let mut sum_squares = 0.0;
+        let mut count = 0;
+        let mut worst = (0, 0.0);
+        for (hour, value) in profile.iter().enumerate() {
+            let normal = climatology.smoothed_shape(day, hour, window);
+            let (Some(value), Some(mean), Some(sd)) = (value, normal.mean(), normal.standard_deviation()) else {
+                continue;
+            };
+            let departure = (value - day_mean) - mean;
+            sum_squares += (departure / sd.max(0.1)).powi(2);
+            count += 1;
+            if departure.abs() > f64::abs(worst.1) {
+                worst = (hour, departure);
+            }
+        }
+        if count == 0 {
+            continue;
+        }
+        let score = (sum_squares / count as f64).sqrt();
+        if score > threshold {
+            abnormal.push(AbnormalDay { day, score, worst_hour: worst.0, worst_departure: worst.1 });
+        }
+    }
+    abnormal
+}
+
+// Prints the mean temperature for every month (rows) and local hour (columns)
+pub fn print_monthly_cycles(station: &str, climatology: &HourlyClimatology) {
+    println!("Typical diurnal cycle at {} ({}, local standard time):", station, units::symbol());
+    print!("{:<4}", "");
+    for hour in 0..24 {
+        print!("{:>6}", hour);
+    }
+    println!();
+    for (month, cycle) in climatology.monthly_cycles().iter().enumerate() {
+        print!("{:<4}", daily::MONTH_NAMES[month]);
+        for (mean, _) in cycle {
+            print!("{:>6.1}", units::temperature(*mean));
+        }
+        println!();
+    }
+}
+
+pub fn print_abnormal_days(station: &str, year: u32, days: &[AbnormalDay]) {
+    println!("Days with an abnormal diurnal cycle at {} in {}: {}", station, year, days.len());
+    for day in days {
+        println!(
+            "  {}: score {:.1}, {:+.1} {} from the usual shape at {:02}:00",
+            daily::format_date(year, day.day),
+            day.score,
+            units::difference(day.worst_departure),
+            units::symbol(),
+            day.worst_hour
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Tenths;
+
+    // Hourly observations for a year of days peaking at 15:00, with the level
+    // of every day different so only the shape repeats. `front` drops the
+    // temperature by 8 °C from noon on that day.
+    fn sine_year(amplitude: f64, front: Option<usize>) -> Vec<TempData> {
+        (0..365 * 24)
+            .map(|hour| {
+                let (day, hour_of_day) = ((hour / 24) as usize, hour % 24);
+                let level = 15.0 + 5.0 * (day as f64 * 0.7).sin();
+                let cycle = amplitude * ((hour_of_day as f64 - 9.0) * std::f64::consts::PI / 12.0).sin();
+                let drop = if front == Some(day) && hour_of_day >= 12 { -8.0 } else { 0.0 };
+                TempData { temp10: Tenths::from_degrees(level + cycle + drop), duration: 60, minute_of_year: hour * 60 }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn only_the_cold_front_day_is_abnormal() {
+        let years = [sine_year(5.0, None), sine_year(5.5, None), sine_year(6.0, None)];
+        let climatology = hourly_climatology(&years, 0);
+        let abnormal = abnormal_days(&sine_year(5.5, Some(100)), &climatology, 7, 2.0);
+        assert_eq!(abnormal.len(), 1);
+        assert_eq!(abnormal[0].day, 100);
+        assert!(abnormal[0].worst_hour >= 12 && abnormal[0].worst_departure < 0.0);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::daily::{self, DailyStats, DailyVariable};
+use crate::stats;
+use crate::units;
+use crate::TempData;
+
+/*
+Run-length event detection on daily aggregates. An event is a run of at least
+`min_duration` consecutive days where the chosen daily variable is beyond a
+threshold, eg:
+    - Heat wave: daily max above the 90th percentile for 3+ days
+    - Cold snap: daily min below the 10th percentile for 3+ days
+    - Freeze: daily min at or below 0 °C
+Thresholds can be absolute or a percentile of the station's own climatology,
+so the same definition can be applied to Austin and to Albany and still give
+comparable results. Days with no data end a run; the end of a year doesn't,
+so an event can start in one year and finish in the next.
+*/
+
+#[derive(Clone, Copy, Debug)]
+pub enum Threshold {
+    Absolute(f64), // °C
+    // Percentile (0..100) of the station's climatology for that calendar day,
+    // using every year provided and a window of ±PERCENTILE_WINDOW days
+    Percentile(f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Direction {
+    Above,
+    Below,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EventDefinition {
+    pub variable: DailyVariable,
+    pub direction: Direction,
+    pub threshold: Threshold,
+    pub min_duration: usize, // days
+}
+
+pub const HEAT_WAVE: EventDefinition = EventDefinition {
+    variable: DailyVariable::Max,
+    direction: Direction::Above,
+    threshold: Threshold::Percentile(90.0),
+    min_duration: 3,
+};
+
+pub const COLD_SNAP: EventDefinition = EventDefinition {
+    variable: DailyVariable::Min,
+    direction: Direction::Below,
+    threshold: Threshold::Percentile(10.0),
+    min_duration: 3,
+};
+
+// Freezing is "at or below" zero, so the threshold sits just above it
+pub const FREEZE: EventDefinition = EventDefinition {
+    variable: DailyVariable::Min,
+    direction: Direction::Below,
+    threshold: Threshold::Absolute(0.05),
+    min_duration: 1,
+};
+
+// Half width of the calendar window used for percentile thresholds
+const PERCENTILE_WINDOW: usize = 7;
+
+#[derive(Clone, Debug)]
+pub struct Event {
+    pub year: u32, // Of the first day
+    pub start: usize, // Day index of the first day
+    pub end_year: u32, // Of the last day
+    pub end: usize, // Day index of the last day (inclusive)
+    pub duration: usize, // days
+    pub peak: f64, // Most extreme value reached, °C
+    // Sum over every day of how far past the threshold it was, in °C·days
+    pub cumulative_intensity: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FreezeDates {
+    pub year: u32,
+    pub last_spring: Option<usize>, // Day index
+    pub first_autumn: Option<usize>, // Day index
+}
+
+// Turns a threshold into one value per day of the year (366 values, °C)
+pub fn resolve_threshold(
+    days_by_year: &[Vec<Option<DailyStats>>],
+    variable: DailyVariable,
+    threshold: Threshold,
+) -> Vec<f64> {
+    match threshold {
+        Threshold::Absolute(value) => vec![value; 366],
+        Threshold::Percentile(percentile) => (0..366)
+            .map(|day| {
+                let mut window = Vec::new();
+                for offset in 0..=(2 * PERCENTILE_WINDOW) {
+                    let other = (day + 366 + offset - PERCENTILE_WINDOW) % 366;
+                    for days in days_by_year {
+                        if let Some(stats) = days[other] {
+                            window.push(stats.get(variable));
+                        }
+                    }
+                }
+                stats::quantile(&window, percentile / 100.0)
+            })
+            .collect(),
+    }
+}
+
+// Finds every event matching `definition` in `years`, where `years[0]` holds
+// the data for `first_year` and each following entry is the next year.
+pub fn detect_events(years: &[Vec<TempData>], first_year: u32, definition: &EventDefinition) -> Vec<Event> {
+    let days_by_year: Vec<Vec<Option<DailyStats>>> = years.iter().map(|t| daily::daily_stats(t)).collect();
+    let thresholds = resolve_threshold(&days_by_year, definition.variable, definition.threshold);
+
+    let mut events = Vec::new();
+    let mut current: Option<Event> = None;
+    for (days, year) in days_by_year.iter().zip(first_year..) {
+        let days_in_year = if daily::is_leap_year(year) { 366 } else { 365 };
+        for (day, stats) in days.iter().enumerate().take(days_in_year) {
+            // How far past the threshold today is. Negative if it isn't.
+            let excess = stats.map(|s| {
+                let value = s.get(definition.variable);
+                let excess = match definition.direction {
+                    Direction::Above => value - thresholds[day],
+                    Direction::Below => thresholds[day] - value,
+                };
+                (value, excess)
+            });
+            match (excess, current.as_mut()) {
+                (Some((value, excess)), Some(event)) if excess > 0.0 => {
+                    event.end_year = year;
+                    event.end = day;
+                    event.duration += 1;
+                    event.cumulative_intensity += excess;
+                    event.peak = match definition.direction {
+                        Direction::Above => event.peak.max(value),
+                        Direction::Below => event.peak.min(value),
+                    };
+                }
+                (Some((value, excess)), None) if excess > 0.0 => {
+                    current = Some(Event {
+                        year,
+                        start: day,
+                        end_year: year,
+                        end: day,
+                        duration: 1,
+                        peak: value,
+                        cumulative_intensity: excess,
+                    });
+                }
+                _ => {
+                    if let Some(event) = current.take() {
+                        if event.duration >= definition.min_duration {
+                            events.push(event);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if let Some(event) = current.take() {
+        if event.duration >= definition.min_duration {
+            events.push(event);
+        }
+    }
+    events
+}
+
+// Last freeze before and first freeze after the middle of the year (July 1st
+// is day index 181 or 182 depending on the year, 182 is close enough).
+pub fn freeze_dates(years: &[Vec<TempData>], first_year: u32) -> Vec<FreezeDates> {
+    const MID_YEAR: usize = 182;
+    let events = detect_events(years, first_year, &FREEZE);
+    (0..years.len())
+        .map(|i| {
+            let year = first_year + i as u32;
+            let last_day = if daily::is_leap_year(year) { 365 } else { 364 };
+            // First and last day of each event within this year, for events
+            // running over New Year
+            let in_year = || {
+                events.iter().filter(move |e| e.year <= year && year <= e.end_year).map(move |e| {
+                    let first = if e.year == year { e.start } else { 0 };
+                    let last = if e.end_year == year { e.end } else { last_day };
+                    (first, last)
+                })
+            };
+            FreezeDates {
+                year,
+                last_spring: in_year().filter(|(first, _)| *first < MID_YEAR).map(|(_, last)| last.min(MID_YEAR - 1)).max(),
+                first_autumn: in_year().filter(|(_, last)| *last >= MID_YEAR).map(|(first, _)| first.max(MID_YEAR)).min(),
+            }
+        })
+        .collect()
+}
+
+// Prints one line per event followed by a per-year summary so that several
+// stations can be compared side by side.
+pub fn print_events(station: &str, name: &str, events: &[Event]) {
+    println!("{} events at {}: {}", name, station, events.len());
+    for event in events {
+        println!(
+            "  {} to {}: {} days, peak {:.1} {}, intensity {:.1} {}·days",
+            daily::format_date(event.year, event.start),
+            daily::format_date(event.end_year, event.end),
+            event.duration,
+            units::temperature(event.peak),
+            units::symbol(),
+            units::difference(event.cumulative_intensity),
+            units::symbol()
+        );
+    }
+    let mut years: Vec<u32> = events.iter().map(|e| e.year).collect();
+    years.dedup();
+    for year in years {
+        let in_year: Vec<&Event> = events.iter().filter(|e| e.year == year).collect();
+        println!(
+            "  {}: {} events, {} days, longest {} days, total intensity {:.1} {}·days",
+            year,
+            in_year.len(),
+            in_year.iter().map(|e| e.duration).sum::<usize>(),
+            in_year.iter().map(|e| e.duration).max().unwrap_or(0),
+            units::difference(in_year.iter().map(|e| e.cumulative_intensity).sum::<f64>()),
+            units::symbol()
+        );
+    }
+}
+
+pub fn print_freeze_dates(station: &str, dates: &[FreezeDates]) {
+    println!("Freeze dates at {}:", station);
+    for date in dates {
+        let format = |day: Option<usize>| day.map_or("none".to_string(), |d| daily::format_date(date.year, d));
+        println!("  {}: last spring freeze {}, first autumn freeze {}", date.year, format(date.last_spring), format(date.first_autumn));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::year_of;
+
+    #[test]
+    fn events_run_over_new_year() {
+        let years = vec![
+            year_of(365, |day| if day >= 363 { -5.0 } else { 10.0 }),
+            year_of(365, |day| if day <= 1 { -5.0 } else { 10.0 }),
+        ];
+        let definition = EventDefinition { min_duration: 3, ..FREEZE };
+        let events = detect_events(&years, 2021, &definition);
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!((event.year, event.start, event.end_year, event.end), (2021, 363, 2022, 1));
+        assert_eq!(event.duration, 4);
+        assert_eq!(event.peak, -5.0);
+
+        let dates = freeze_dates(&years, 2021);
+        assert_eq!(dates[0].first_autumn, Some(363));
+        assert_eq!(dates[1].last_spring, Some(1));
+    }
+}
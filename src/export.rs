@@ -0,0 +1,134 @@
+use crate::daily;
+use crate::strangeness::Metric;
+use crate::units;
+use crate::TempData;
+
+/*
+CSV and JSON export of the daily statistics and strangeness metrics, so the
+results can be picked up by a spreadsheet or a web front end. Temperatures are
+written in the unit chosen with units::set_unit(), which is named in the CSV
+header and in the JSON "unit" field. Both formats are written by hand, they're
+simple enough not to need a serialisation library.
+*/
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+// JSON has no NaN or infinity
+fn json_number(value: f64, decimals: usize) -> String {
+    if value.is_finite() {
+        format!("{:.*}", decimals, value)
+    } else {
+        "null".to_string()
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// (date, max, min, mean, minutes covered) for every day with data, in the
+// chosen unit
+fn daily_rows(years: &[Vec<TempData>], first_year: u32) -> Vec<(String, f64, f64, f64, u32)> {
+    let mut rows = Vec::new();
+    for (temps, year) in years.iter().zip(first_year..) {
+        for (day, stats) in daily::daily_stats(temps).iter().enumerate() {
+            if let Some(stats) = stats {
+                rows.push((
+                    daily::format_date(year, day),
+                    units::temperature(stats.max),
+                    units::temperature(stats.min),
+                    units::temperature(stats.mean),
+                    stats.minutes,
+                ));
+            }
+        }
+    }
+    rows
+}
+
+pub fn daily_csv(years: &[Vec<TempData>], first_year: u32) -> String {
+    let symbol = units::symbol();
+    let mut csv = format!("date,max ({0}),min ({0}),mean ({0}),minutes\n", symbol);
+    for (date, max, min, mean, minutes) in daily_rows(years, first_year) {
+        csv.push_str(&format!("{},{:.1},{:.1},{:.2},{}\n", date, max, min, mean, minutes));
+    }
+    csv
+}
+
+pub fn daily_json(station: &str, years: &[Vec<TempData>], first_year: u32) -> String {
+    let days: Vec<String> = daily_rows(years, first_year)
+        .into_iter()
+        .map(|(date, max, min, mean, minutes)| {
+            format!(
+                "{{\"date\":\"{}\",\"max\":{},\"min\":{},\"mean\":{},\"minutes\":{}}}",
+                date,
+                json_number(max, 1),
+                json_number(min, 1),
+                json_number(mean, 2),
+                minutes
+            )
+        })
+        .collect();
+    format!(
+        "{{\"station\":{},\"unit\":{},\"days\":[{}]}}",
+        json_string(station),
+        json_string(units::symbol()),
+        days.join(",")
+    )
+}
+
+// One row per station and metric
+pub fn metrics_csv(stations: &[(&str, Vec<Metric>)]) -> String {
+    let mut csv = String::from("station,metric,value,unit\n");
+    for (station, metrics) in stations {
+        for metric in metrics {
+            csv.push_str(&format!(
+                "{},{},{:.4},{}\n",
+                csv_field(station),
+                csv_field(&metric.name),
+                metric.value,
+                csv_field(metric.unit)
+            ));
+        }
+    }
+    csv
+}
+
+pub fn metrics_json(stations: &[(&str, Vec<Metric>)]) -> String {
+    let stations: Vec<String> = stations
+        .iter()
+        .map(|(station, metrics)| {
+            let metrics: Vec<String> = metrics
+                .iter()
+                .map(|m| {
+                    format!(
+                        "{{\"name\":{},\"value\":{},\"unit\":{}}}",
+                        json_string(&m.name),
+                        json_number(m.value, 4),
+                        json_string(m.unit)
+                    )
+                })
+                .collect();
+            format!("{{\"station\":{},\"metrics\":[{}]}}", json_string(station), metrics.join(","))
+        })
+        .collect();
+    format!("[{}]", stations.join(","))
+}
@@ -0,0 +1,390 @@
+use crate::daily;
+use crate::stats;
+use crate::units;
+use crate::TempData;
+
+/*
+Extreme value analysis. Two approaches are supported:
+    - Block maxima: fit a Generalised Extreme Value (GEV) distribution to the
+      hottest temperature of every year.
+    - Peaks over threshold: fit a Generalised Pareto Distribution (GPD) to the
+      declustered daily maxima which exceed a threshold.
+Both can be fit by maximum likelihood or by L-moments. The shape parameter
+follows the Coles convention: positive means a heavy upper tail, negative
+means a bounded upper tail (which is what temperatures usually show).
+All temperatures in here are in °C.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FitMethod {
+    MaximumLikelihood,
+    LMoments,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Distribution {
+    Gev,
+    // threshold is in °C, rate is the mean number of exceedances per year
+    Gpd { threshold: f64, rate: f64 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ExtremeFit {
+    pub distribution: Distribution,
+    pub method: FitMethod,
+    pub location: f64, // Always 0 for the GPD, the threshold takes its place
+    pub scale: f64,
+    pub shape: f64,
+    pub sample_size: usize,
+}
+
+// The return periods (in years) which are reported by default
+pub const STANDARD_RETURN_PERIODS: [f64; 3] = [10.0, 50.0, 100.0];
+
+// Below this the shape parameter is treated as zero (Gumbel / exponential) to
+// avoid dividing by a tiny number.
+const SHAPE_EPSILON: f64 = 1e-6;
+
+impl ExtremeFit {
+    // Probability that a single annual maximum (GEV) or a single exceedance
+    // (GPD) is at or below `value`.
+    fn cdf(&self, value: f64) -> f64 {
+        match self.distribution {
+            Distribution::Gev => {
+                let z = (value - self.location) / self.scale;
+                if self.shape.abs() < SHAPE_EPSILON {
+                    return (-(-z).exp()).exp();
+                }
+                let t = 1.0 + self.shape * z;
+                if t <= 0.0 {
+                    // Beyond the end of the support
+                    return if self.shape > 0.0 { 0.0 } else { 1.0 };
+                }
+                (-t.powf(-1.0 / self.shape)).exp()
+            }
+            Distribution::Gpd { threshold, .. } => {
+                let y = value - threshold;
+                if y <= 0.0 {
+                    return 0.0;
+                }
+                if self.shape.abs() < SHAPE_EPSILON {
+                    return 1.0 - (-y / self.scale).exp();
+                }
+                let t = 1.0 + self.shape * y / self.scale;
+                if t <= 0.0 {
+                    return 1.0;
+                }
+                1.0 - t.powf(-1.0 / self.shape)
+            }
+        }
+    }
+
+    // Temperature which is exceeded on average once every `years` years
+    pub fn return_level(&self, years: f64) -> f64 {
+        match self.distribution {
+            Distribution::Gev => {
+                let y = -(1.0 - 1.0 / years).ln();
+                if self.shape.abs() < SHAPE_EPSILON {
+                    return self.location - self.scale * y.ln();
+                }
+                self.location - self.scale / self.shape * (1.0 - y.powf(-self.shape))
+            }
+            Distribution::Gpd { threshold, rate } => {
+                let m = years * rate;
+                if m <= 1.0 {
+                    return threshold;
+                }
+                if self.shape.abs() < SHAPE_EPSILON {
+                    return threshold + self.scale * m.ln();
+                }
+                threshold + self.scale / self.shape * (m.powf(self.shape) - 1.0)
+            }
+        }
+    }
+
+    // Average number of years between events at least as hot as `value`.
+    // Returns infinity for values beyond the upper end of the fitted
+    // distribution.
+    pub fn return_period(&self, value: f64) -> f64 {
+        let exceedance = 1.0 - self.cdf(value);
+        match self.distribution {
+            Distribution::Gev => 1.0 / exceedance,
+            Distribution::Gpd { threshold, rate } => {
+                if value <= threshold {
+                    // Not an extreme by this definition
+                    return 1.0 / rate;
+                }
+                1.0 / (rate * exceedance)
+            }
+        }
+    }
+
+    pub fn return_levels(&self, periods: &[f64]) -> Vec<(f64, f64)> {
+        periods.iter().map(|&years| (years, self.return_level(years))).collect()
+    }
+}
+
+// The hottest temperature of every year. Each element of `years` is one
+// year of data for the same station. Years without data are skipped.
+pub fn annual_maxima(years: &[Vec<TempData>]) -> Vec<f64> {
+    years
+        .iter()
+        .filter_map(|temps| temps.iter().map(|t| t.temp10).max())
+        .map(|max| max.degrees())
+        .collect()
+}
+
+// Declustered peaks over a threshold. Daily maxima above `threshold` are
+// grouped into clusters, and a cluster only ends once `run_length` consecutive
+// days have stayed at or below the threshold. Only the peak of each cluster is
+// kept so that a single heat wave does not count as several independent
+// extremes. Clusters carry on from one year into the next, `years` being
+// consecutive. Returns the cluster peaks in °C.
+pub fn peaks_over_threshold(years: &[Vec<TempData>], threshold: f64, run_length: usize) -> Vec<f64> {
+    let mut peaks = Vec::new();
+    let mut current_peak: Option<f64> = None;
+    let mut days_below = 0;
+    for temps in years {
+        for max in daily::daily_stats(temps).into_iter().flatten().map(|d| d.max) {
+            if max > threshold {
+                current_peak = Some(current_peak.map_or(max, |p| p.max(max)));
+                days_below = 0;
+            } else if let Some(peak) = current_peak {
+                days_below += 1;
+                if days_below >= run_length {
+                    peaks.push(peak);
+                    current_peak = None;
+                }
+            }
+        }
+    }
+    if let Some(peak) = current_peak {
+        peaks.push(peak);
+    }
+    peaks
+}
+
+pub fn fit_gev(maxima: &[f64], method: FitMethod) -> Option<ExtremeFit> {
+    let (l1, l2, l3) = stats::l_moments(maxima)?;
+    // Hosking's L-moment estimator. Also used as the starting point for the
+    // likelihood maximisation since it is always in the valid region.
+    let t3 = l3 / l2;
+    let c = 2.0 / (3.0 + t3) - 2f64.ln() / 3f64.ln();
+    let k = 7.8590 * c + 2.9554 * c * c;
+    let (location, scale) = if k.abs() < SHAPE_EPSILON {
+        let scale = l2 / 2f64.ln();
+        (l1 - 0.5772 * scale, scale)
+    } else {
+        let scale = l2 * k / ((1.0 - 2f64.powf(-k)) * stats::gamma(1.0 + k));
+        (l1 - scale * (1.0 - stats::gamma(1.0 + k)) / k, scale)
+    };
+    let mut fit = ExtremeFit {
+        distribution: Distribution::Gev,
+        method,
+        location,
+        scale,
+        shape: -k, // Hosking uses the opposite sign convention
+        sample_size: maxima.len(),
+    };
+    if method == FitMethod::MaximumLikelihood {
+        let params = stats::nelder_mead(
+            |p| gev_negative_log_likelihood(maxima, p[0], p[1], p[2]),
+            &[fit.location, fit.scale, fit.shape],
+            &[0.1 * fit.scale, 0.1 * fit.scale, 0.05],
+            2000,
+        );
+        fit.location = params[0];
+        fit.scale = params[1];
+        fit.shape = params[2];
+    }
+    if fit.scale.is_finite() && fit.scale > 0.0 {
+        Some(fit)
+    } else {
+        None
+    }
+}
+
+// `peaks` are the declustered exceedances from `peaks_over_threshold` and
+// `num_years` is how many years of data they were drawn from.
+pub fn fit_gpd(peaks: &[f64], threshold: f64, num_years: usize, method: FitMethod) -> Option<ExtremeFit> {
+    let excesses: Vec<f64> = peaks.iter().map(|p| p - threshold).filter(|y| *y > 0.0).collect();
+    let (l1, l2, _) = stats::l_moments(&excesses)?;
+    // L-moment estimator with a known lower bound of zero
+    let k = l1 / l2 - 2.0;
+    let mut fit = ExtremeFit {
+        distribution: Distribution::Gpd { threshold, rate: excesses.len() as f64 / num_years as f64 },
+        method,
+        location: 0.0,
+        scale: (1.0 + k) * l1,
+        shape: -k,
+        sample_size: excesses.len(),
+    };
+    if method == FitMethod::MaximumLikelihood {
+        let params = stats::nelder_mead(
+            |p| gpd_negative_log_likelihood(&excesses, p[0], p[1]),
+            &[fit.scale, fit.shape],
+            &[0.1 * fit.scale, 0.05],
+            2000,
+        );
+        fit.scale = params[0];
+        fit.shape = params[1];
+    }
+    if fit.scale.is_finite() && fit.scale > 0.0 {
+        Some(fit)
+    } else {
+        None
+    }
+}
+
+fn gev_negative_log_likelihood(maxima: &[f64], location: f64, scale: f64, shape: f64) -> f64 {
+    if scale <= 0.0 {
+        return f64::INFINITY;
+    }
+    let mut nll = maxima.len() as f64 * scale.ln();
+    for x in maxima {
+        let z = (x - location) / scale;
+        if shape.abs() < SHAPE_EPSILON {
+            nll += z + (-z).exp();
+            continue;
+        }
+        let t = 1.0 + shape * z;
+        if t <= 0.0 {
+            return f64::INFINITY;
+        }
+        nll += (1.0 + 1.0 / shape) * t.ln() + t.powf(-1.0 / shape);
+    }
+    nll
+}
+
+fn gpd_negative_log_likelihood(excesses: &[f64], scale: f64, shape: f64) -> f64 {
+    if scale <= 0.0 {
+        return f64::INFINITY;
+    }
+    let mut nll = excesses.len() as f64 * scale.ln();
+    for y in excesses {
+        if shape.abs() < SHAPE_EPSILON {
+            nll += y / scale;
+            continue;
+        }
+        let t = 1.0 + shape * y / scale;
+        if t <= 0.0 {
+            return f64::INFINITY;
+        }
+        nll += (1.0 + 1.0 / shape) * t.ln();
+    }
+    nll
+}
+
+// Prints the fitted parameters, the standard return levels, and how rare the
+// `observed` temperature (in °C) is, all in the chosen unit.
+// eg. "44.0 °C was a 1-in-80-year event at Camp Mabry"
+pub fn print_extremes_report(station: &str, fit: &ExtremeFit, observed: Option<f64>) {
+    // The GPD's location is relative to its threshold
+    let (name, location) = match fit.distribution {
+        Distribution::Gev => ("GEV (annual maxima)".to_string(), fit.location),
+        Distribution::Gpd { threshold, rate } => (
+            format!("GPD (peaks over {:.1} {}, {:.2} events/year)", units::temperature(threshold), units::symbol(), rate),
+            threshold + fit.location,
+        ),
+    };
+    println!(
+        "{} {} fit by {:?}: location {:.2} {}, scale {:.2} {}, shape {:.3} (n = {})",
+        station,
+        name,
+        fit.method,
+        units::temperature(location),
+        units::symbol(),
+        units::difference(fit.scale),
+        units::symbol(),
+        fit.shape,
+        fit.sample_size
+    );
+    for (years, level) in fit.return_levels(&STANDARD_RETURN_PERIODS) {
+        println!("  {}-year return level: {:.1} {}", years, units::temperature(level), units::symbol());
+    }
+    if let Some(value) = observed {
+        let period = fit.return_period(value);
+        if period.is_finite() {
+            println!("  {:.1} {} was a 1-in-{:.0}-year event at {}", units::temperature(value), units::symbol(), period, station);
+        } else {
+            println!("  {:.1} {} is beyond anything the fit for {} allows", units::temperature(value), units::symbol(), station);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::year_of;
+
+    #[test]
+    fn clusters_run_over_new_year() {
+        let years = vec![
+            year_of(365, |day| if day == 364 { 40.0 } else { 20.0 }),
+            year_of(365, |day| if day == 1 { 42.0 } else { 20.0 }),
+        ];
+        // One day at or below the threshold isn't enough to end the cluster
+        assert_eq!(peaks_over_threshold(&years, 30.0, 3), vec![42.0]);
+        assert_eq!(peaks_over_threshold(&years, 30.0, 1), vec![40.0, 42.0]);
+    }
+
+    fn gev(location: f64, scale: f64, shape: f64) -> ExtremeFit {
+        ExtremeFit { distribution: Distribution::Gev, method: FitMethod::LMoments, location, scale, shape, sample_size: 0 }
+    }
+
+    #[test]
+    fn gev_return_levels_match_the_closed_form() {
+        // Gumbel: x = location - scale * ln(-ln(1 - 1/T))
+        let gumbel = gev(30.0, 2.0, 0.0);
+        assert!((gumbel.return_level(100.0) - 39.2003).abs() < 1e-3);
+        assert!((gumbel.return_level(10.0) - 34.5007).abs() < 1e-3);
+        // Bounded above at location - scale / shape = 40
+        let weibull = gev(30.0, 2.0, -0.2);
+        assert!((weibull.return_level(100.0) - 36.0149).abs() < 1e-3);
+        assert!(weibull.return_level(1e6) < 40.0);
+        assert!(weibull.return_period(40.5).is_infinite());
+        for fit in [gumbel, weibull] {
+            for years in STANDARD_RETURN_PERIODS {
+                assert!((fit.return_period(fit.return_level(years)) - years).abs() < 1e-6 * years);
+            }
+        }
+    }
+
+    #[test]
+    fn gpd_return_levels_match_the_closed_form() {
+        let fit = |shape| ExtremeFit {
+            distribution: Distribution::Gpd { threshold: 35.0, rate: 2.0 },
+            method: FitMethod::LMoments,
+            location: 0.0,
+            scale: 1.5,
+            shape,
+            sample_size: 0,
+        };
+        // x = threshold + scale * ln(T * rate) for an exponential tail
+        assert!((fit(0.0).return_level(10.0) - 39.4936).abs() < 1e-3);
+        // x = threshold + scale / shape * ((T * rate)^shape - 1)
+        assert!((fit(-0.25).return_level(50.0) - 39.1026).abs() < 1e-3);
+        assert_eq!(fit(-0.25).return_level(0.25), 35.0);
+        assert!((fit(-0.25).return_period(39.1026) - 50.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn fits_recover_the_parameters_of_their_own_quantiles() {
+        // Evenly spread quantiles of a Gumbel(30, 2) and an exponential tail
+        // with scale 1.5 above 35 °C over 50 years
+        let probabilities: Vec<f64> = (1..=200).map(|i| (i as f64 - 0.5) / 200.0).collect();
+        let maxima: Vec<f64> = probabilities.iter().map(|p| 30.0 - 2.0 * (-p.ln()).ln()).collect();
+        let peaks: Vec<f64> = probabilities.iter().map(|p| 35.0 - 1.5 * (1.0 - p).ln()).collect();
+        for method in [FitMethod::LMoments, FitMethod::MaximumLikelihood] {
+            let fit = fit_gev(&maxima, method).unwrap();
+            assert!((fit.location - 30.0).abs() < 0.1, "{:?}", fit);
+            assert!((fit.scale - 2.0).abs() < 0.1, "{:?}", fit);
+            assert!(fit.shape.abs() < 0.05, "{:?}", fit);
+            let fit = fit_gpd(&peaks, 35.0, 50, method).unwrap();
+            assert!((fit.scale - 1.5).abs() < 0.1, "{:?}", fit);
+            assert!(fit.shape.abs() < 0.05, "{:?}", fit);
+            // 4 peaks a year, so the 10-year level is 35 + 1.5 * ln(40)
+            assert!((fit.return_level(10.0) - 40.5333).abs() < 0.3, "{:?}", fit);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use plotters::style::RGBColor;
+
+use crate::chart::{self, Axis, ChartSpec, ColourBar, ColourMap, Grid, Series, SeriesData, Theme};
+use crate::daily::{self, DailyVariable};
+use crate::normals::DailyNormal;
+use crate::units;
+use crate::TempData;
+
+/*
+Heatmaps of daily anomalies (the day's value minus its normal, see normals.rs)
+for spotting strange periods at a glance:
+    - AnomalyHeatmap: day of year across, one row per year, so a whole record
+      fits in one picture and long warm or cold spells stand out as streaks.
+    - AnomalyCalendar: a single year laid out GitHub style, one column per
+      week.
+Both colour the anomaly on a diverging scale which saturates at ±limit, and
+are drawn through a ChartSpec (see spec()) with a colour bar beside them.
+*/
+
+// Anomaly of `variable` for every calendar day (see daily::calendar_day) of
+// every year from first_year, None where there is no data or no normal
+pub fn daily_anomalies(years: &[Vec<TempData>], first_year: u32, normals: &[Option<DailyNormal>], variable: DailyVariable) -> Vec<Vec<Option<f64>>> {
+    years
+        .iter()
+        .zip(first_year..)
+        .map(|(temps, year)| {
+            daily::calendar_daily_stats(temps, year)
+                .iter()
+                .zip(normals)
+                .map(|(stats, normal)| Some(stats.as_ref()?.get(variable) - normal.as_ref()?.mean))
+                .collect()
+        })
+        .collect()
+}
+
+pub struct AnomalyHeatmap {
+    pub first_year: u32,
+    pub anomalies: Vec<Vec<Option<f64>>>, // One row per year from first_year, °C
+    pub limit: f64, // °C, anomalies beyond ±limit get the end colours
+    pub colour_map: ColourMap,
+    pub title: String,
+    pub theme: Theme,
+}
+
+impl AnomalyHeatmap {
+    pub fn new(first_year: u32, anomalies: Vec<Vec<Option<f64>>>, limit: f64) -> AnomalyHeatmap {
+        AnomalyHeatmap {
+            first_year,
+            anomalies,
+            limit,
+            colour_map: chart::BLUE_WHITE_RED,
+            title: "Daily temperature anomalies".to_string(),
+            theme: Theme::dark(),
+        }
+    }
+}
+
+fn colour_bar(colour_map: ColourMap, limit: f64) -> ColourBar {
+    let limit = units::difference(limit);
+    ColourBar { map: colour_map, range: -limit..limit, description: format!("Anomaly ({})", units::symbol()) }
+}
+
+impl AnomalyHeatmap {
+    pub fn spec(&self) -> ChartSpec {
+        let last_year = self.first_year as f64 + self.anomalies.len() as f64;
+        let x = Axis { range: 0.0..366.0, description: "Day of Year".to_string(), labels: 12, formatter: |day| format!("{:.0}", day) };
+        let y = Axis {
+            range: self.first_year as f64..last_year,
+            description: "Year".to_string(),
+            labels: self.anomalies.len().min(15),
+            formatter: |year| if year.fract() == 0.0 { format!("{:.0}", year) } else { String::new() },
+        };
+        let cells = self
+            .anomalies
+            .iter()
+            .zip(self.first_year..)
+            .flat_map(|(days, year)| {
+                let year = year as f64;
+                days.iter().enumerate().filter_map(move |(day, anomaly)| {
+                    let colour = self.colour_map.diverging((*anomaly)?, self.limit);
+                    Some((day as f64, year, day as f64 + 1.0, year + 1.0, colour))
+                })
+            })
+            .collect();
+        let mut spec = ChartSpec::new(x, y);
+        spec.title = Some(self.title.clone());
+        spec.theme = self.theme;
+        spec.grid = Grid::Off;
+        spec.series.push(Series { name: String::new(), color: self.theme.foreground, data: SeriesData::Cells { cells } });
+        spec.colour_bar = Some(colour_bar(self.colour_map, self.limit));
+        spec
+    }
+}
+
+pub struct AnomalyCalendar {
+    pub year: u32,
+    pub anomalies: Vec<Option<f64>>, // By calendar day, °C
+    pub limit: f64,
+    pub colour_map: ColourMap,
+}
+
+impl AnomalyCalendar {
+    pub fn new(year: u32, anomalies: Vec<Option<f64>>, limit: f64) -> AnomalyCalendar {
+        AnomalyCalendar { year, anomalies, limit, colour_map: chart::BLUE_WHITE_RED }
+    }
+
+    pub fn spec(&self) -> ChartSpec {
+        // The calendar is laid out by day of year, so 29 February is skipped
+        // outside leap years
+        let days_in_year = if daily::is_leap_year(self.year) { 366 } else { 365 };
+        let colours: Vec<Option<RGBColor>> = (0..days_in_year)
+            .map(|day| {
+                let anomaly = self.anomalies.get(daily::calendar_day(self.year, day)).copied().flatten()?;
+                Some(self.colour_map.diverging(anomaly, self.limit))
+            })
+            .collect();
+        let mut spec = ChartSpec::calendar(self.year, &colours);
+        spec.title = Some(format!("Daily temperature anomalies in {}", self.year));
+        spec.colour_bar = Some(colour_bar(self.colour_map, self.limit));
+        spec
+    }
+}
@@ -0,0 +1,264 @@
+use crate::daily;
+use crate::isd;
+use crate::units::{self, Tenths};
+use crate::TempData;
+
+/*
+Humidity and how hot or cold it feels, from the dew point next to the air
+temperature in each record (and the wind, for wind chill and apparent
+temperature):
+    - relative humidity, from the Magnus formula with the Alduchov and
+      Eskridge (1996) constants;
+    - heat index, the NWS version: Steadman's simple formula, or Rothfusz's
+      regression with its adjustments once it is warm enough to matter;
+    - wind chill, the 2001 North American index, only defined at or below
+      10 °C with wind of at least 4.8 km/h;
+    - apparent temperature, Steadman's (1994) version as used by the Bureau of
+      Meteorology, which takes humidity and wind into account at any
+      temperature.
+Dew point and the derived temperatures come out as TempData, so everything
+written for air temperature (daily stats, normals, trends, STL, strangeness)
+works on them unchanged. Relative humidity isn't a temperature, so it has
+its own daily and monthly means here.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DerivedTemperature {
+    DewPoint,
+    HeatIndex,
+    WindChill,
+    ApparentTemperature,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Observation {
+    pub minute_of_year: u32,
+    pub duration: u16, // Minutes until the next observation
+    pub temp: f64, // °C
+    pub dew_point: f64, // °C
+    pub wind: Option<f64>, // m/s
+}
+
+// Saturation vapour pressure (hPa) over water at `temp` °C
+fn saturation_vapour_pressure(temp: f64) -> f64 {
+    6.1094 * (17.625 * temp / (temp + 243.04)).exp()
+}
+
+// Relative humidity (%) from the air temperature and dew point, °C
+pub fn relative_humidity(temp: f64, dew_point: f64) -> f64 {
+    (100.0 * saturation_vapour_pressure(dew_point) / saturation_vapour_pressure(temp)).min(100.0)
+}
+
+// Heat index (°C) from the air temperature (°C) and relative humidity (%)
+pub fn heat_index(temp: f64, humidity: f64) -> f64 {
+    let t = temp * 9.0 / 5.0 + 32.0;
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + humidity * 0.094);
+    let fahrenheit = if (simple + t) / 2.0 < 80.0 {
+        simple
+    } else {
+        let rh = humidity;
+        let mut index = -42.379 + 2.049_015_23 * t + 10.143_331_27 * rh - 0.224_755_41 * t * rh - 0.006_837_83 * t * t
+            - 0.054_817_17 * rh * rh
+            + 0.001_228_74 * t * t * rh
+            + 0.000_852_82 * t * rh * rh
+            - 0.000_001_99 * t * t * rh * rh;
+        if rh < 13.0 && (80.0..=112.0).contains(&t) {
+            index -= (13.0 - rh) / 4.0 * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+        } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+            index += (rh - 85.0) / 10.0 * ((87.0 - t) / 5.0);
+        }
+        index
+    };
+    (fahrenheit - 32.0) * 5.0 / 9.0
+}
+
+// Wind chill (°C) from the air temperature (°C) and wind speed (m/s). Outside
+// the range the index is defined for this is just the air temperature.
+pub fn wind_chill(temp: f64, wind: f64) -> f64 {
+    let speed = wind * 3.6; // km/h
+    if temp > 10.0 || speed < 4.8 {
+        return temp;
+    }
+    let factor = speed.powf(0.16);
+    13.12 + 0.6215 * temp - 11.37 * factor + 0.3965 * temp * factor
+}
+
+// Apparent temperature (°C) from the air temperature (°C), relative humidity
+// (%) and wind speed (m/s)
+pub fn apparent_temperature(temp: f64, humidity: f64, wind: f64) -> f64 {
+    let vapour_pressure = humidity / 100.0 * 6.105 * (17.27 * temp / (237.7 + temp)).exp();
+    temp + 0.33 * vapour_pressure - 0.70 * wind - 4.00
+}
+
+impl Observation {
+    pub fn relative_humidity(&self) -> f64 {
+        relative_humidity(self.temp, self.dew_point)
+    }
+
+    // None for the wind based temperatures when the wind is missing
+    pub fn get(&self, variable: DerivedTemperature) -> Option<f64> {
+        match variable {
+            DerivedTemperature::DewPoint => Some(self.dew_point),
+            DerivedTemperature::HeatIndex => Some(heat_index(self.temp, self.relative_humidity())),
+            DerivedTemperature::WindChill => Some(wind_chill(self.temp, self.wind?)),
+            DerivedTemperature::ApparentTemperature => Some(apparent_temperature(self.temp, self.relative_humidity(), self.wind?)),
+        }
+    }
+}
+
+// Every record with a valid air temperature and dew point, checked the same
+// way as the main air temperature series. Like the air temperatures, each
+// lasts until the next one and the last lasts until the end of its day.
+pub fn extract_observations(raw_data: &[String]) -> Vec<Observation> {
+    let mut observations: Vec<Observation> = raw_data
+        .iter()
+        .filter_map(|line| {
+            Some(Observation {
+                minute_of_year: isd::minute_of_year(line),
+                duration: 0,
+                temp: isd::air_temperature(line)?.degrees(),
+                dew_point: isd::dew_point(line)?.degrees(),
+                wind: isd::wind(line).map(|w| w.speed),
+            })
+        })
+        .collect();
+    for i in 0..observations.len() {
+        let minute = observations[i].minute_of_year;
+        let next = observations.get(i + 1).map_or(minute - minute % 1440 + 1440, |o| o.minute_of_year);
+        observations[i].duration = next.saturating_sub(minute).min(u16::MAX as u32) as u16;
+    }
+    observations
+}
+
+// `variable` as a temperature series, for the air temperature pipeline.
+// Observations without the wind a variable needs are dropped, and their time
+// goes to the observation before them.
+pub fn derived_temps(observations: &[Observation], variable: DerivedTemperature) -> Vec<TempData> {
+    let mut temps: Vec<TempData> = Vec::with_capacity(observations.len());
+    for observation in observations {
+        match observation.get(variable) {
+            Some(value) => temps.push(TempData {
+                temp10: Tenths::from_degrees(value),
+                duration: observation.duration,
+                minute_of_year: observation.minute_of_year,
+            }),
+            None => {
+                if let Some(previous) = temps.last_mut() {
+                    previous.duration = previous.duration.saturating_add(observation.duration);
+                }
+            }
+        }
+    }
+    temps
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MonthlyHumidity {
+    pub relative_humidity: f64, // %, time weighted mean
+    pub dew_point: f64, // °C, time weighted mean
+    pub max_heat_index: f64, // °C
+    pub min_wind_chill: Option<f64>, // °C, None when the wind was never known
+}
+
+pub fn monthly_humidity(year: u32, observations: &[Observation]) -> [Option<MonthlyHumidity>; 12] {
+    let mut months: [Vec<&Observation>; 12] = Default::default();
+    for observation in observations {
+        let (month, _) = daily::month_and_day(year, crate::get_day_index_from_minutes(observation.minute_of_year));
+        months[month - 1].push(observation);
+    }
+    months.map(|observations| {
+        let minutes: f64 = observations.iter().map(|o| o.duration as f64).sum();
+        if minutes <= 0.0 {
+            return None;
+        }
+        let weighted = |value: &dyn Fn(&Observation) -> f64| observations.iter().map(|o| value(o) * o.duration as f64).sum::<f64>() / minutes;
+        let derived = |variable| observations.iter().filter_map(move |o| o.get(variable));
+        Some(MonthlyHumidity {
+            relative_humidity: weighted(&|o| o.relative_humidity()),
+            dew_point: weighted(&|o| o.dew_point),
+            max_heat_index: derived(DerivedTemperature::HeatIndex).fold(f64::NEG_INFINITY, f64::max),
+            min_wind_chill: derived(DerivedTemperature::WindChill).reduce(f64::min),
+        })
+    })
+}
+
+pub fn print_monthly_humidity(station: &str, year: u32, months: &[Option<MonthlyHumidity>; 12]) {
+    let symbol = units::symbol();
+    println!("{} humidity in {}", station, year);
+    println!(
+        "{:<6}{:>8}{:>14}{:>16}{:>16}",
+        "Month",
+        "RH (%)",
+        format!("Dew pt ({})", symbol),
+        format!("Max HI ({})", symbol),
+        format!("Min WC ({})", symbol)
+    );
+    for (name, month) in daily::MONTH_NAMES.iter().zip(months) {
+        let Some(month) = month else {
+            println!("{:<6}{:>8}", name, "-");
+            continue;
+        };
+        let wind_chill = month.min_wind_chill.map_or("-".to_string(), |wc| format!("{:.1}", units::temperature(wc)));
+        println!(
+            "{:<6}{:>8.0}{:>14.1}{:>16.1}{:>16}",
+            name,
+            month.relative_humidity,
+            units::temperature(month.dew_point),
+            units::temperature(month.max_heat_index),
+            wind_chill
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn celsius(fahrenheit: f64) -> f64 {
+        (fahrenheit - 32.0) * 5.0 / 9.0
+    }
+
+    fn fahrenheit(celsius: f64) -> f64 {
+        celsius * 9.0 / 5.0 + 32.0
+    }
+
+    #[test]
+    fn heat_index_matches_the_nws_table() {
+        // (°F, % relative humidity, heat index in whole °F)
+        let table = [
+            (80.0, 40.0, 80.0),
+            (86.0, 90.0, 105.0),
+            (90.0, 70.0, 106.0),
+            (96.0, 65.0, 121.0),
+            (100.0, 50.0, 118.0),
+            (104.0, 40.0, 119.0),
+        ];
+        for (temp, humidity, table) in table {
+            let index = fahrenheit(heat_index(celsius(temp), humidity));
+            assert!((index - table).abs() <= 0.5, "{} °F at {}%: {} against {}", temp, humidity, index, table);
+        }
+    }
+
+    #[test]
+    fn wind_chill_matches_the_nws_and_eccc_tables() {
+        // (°F, mph, wind chill in whole °F) from the NWS chart
+        for (temp, mph, table) in [(30.0, 5.0, 25.0), (5.0, 10.0, -10.0), (0.0, 15.0, -19.0), (-10.0, 20.0, -35.0)] {
+            let chill = fahrenheit(wind_chill(celsius(temp), mph * 0.44704));
+            assert!((chill - table).abs() <= 0.5, "{} °F at {} mph: {} against {}", temp, mph, chill, table);
+        }
+        // (°C, km/h, wind chill in whole °C) from Environment Canada's table
+        for (temp, kmh, table) in [(-10.0, 20.0, -18.0), (-20.0, 30.0, -33.0)] {
+            let chill = wind_chill(temp, kmh / 3.6);
+            assert!((chill - table).abs() <= 0.5, "{} °C at {} km/h: {} against {}", temp, kmh, chill, table);
+        }
+        // Not defined when it is warm or calm
+        assert_eq!(wind_chill(15.0, 10.0), 15.0);
+        assert_eq!(wind_chill(-5.0, 1.0), -5.0);
+    }
+
+    #[test]
+    fn relative_humidity_from_the_dew_point() {
+        assert!((relative_humidity(20.0, 10.0) - 52.5).abs() < 0.1);
+        assert_eq!(relative_humidity(15.0, 15.0), 100.0);
+    }
+}
@@ -0,0 +1,220 @@
+use crate::diurnal::HourlyClimatology;
+use crate::resample::{ResampledSeries, Sample};
+use crate::stats;
+use crate::units::Tenths;
+use crate::TempData;
+
+/*
+Gap filling. Missing points of a resampled series can be filled from:
+    - Climatology: the station's usual temperature for that day and local
+      hour, shifted by the anomaly of the observations either side of the gap
+      so that a gap during a cold snap isn't filled with normal weather.
+    - Neighbours: a linear regression on a nearby station which is well
+      correlated with this one, fit separately for every month.
+Every filled point is marked Sample::Imputed, and coverage() keeps observed,
+interpolated and imputed minutes apart so aggregates computed on the filled
+series don't hide how much of it was made up.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strategy {
+    Climatology,
+    Neighbours,
+    // Neighbours first, then climatology for whatever is left
+    Both,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Coverage {
+    pub observed: u32, // minutes
+    pub interpolated: u32,
+    pub imputed: u32,
+    pub missing: u32,
+}
+
+// Linear fit of this station on a neighbour for one month
+#[derive(Clone, Copy, Debug)]
+struct Regression {
+    intercept: f64,
+    slope: f64,
+    correlation: f64,
+}
+
+// How far either side of a gap to look for observations to anchor the
+// climatology to, in samples
+const ANOMALY_SEARCH: usize = 24;
+// Days either side pooled into the climatology for a single day
+const CLIMATOLOGY_WINDOW: usize = 7;
+
+fn month_of_sample(series: &ResampledSeries, year: u32, index: usize) -> usize {
+    let day = crate::get_day_index_from_minutes(series.minute_of_year(index));
+    crate::daily::month_and_day(year, day).0 - 1
+}
+
+// Least squares fit of `target` on `neighbour` for every calendar month, using
+// only points where both have a real (not imputed) value.
+fn monthly_regressions(target: &ResampledSeries, neighbour: &ResampledSeries, year: u32) -> [Option<Regression>; 12] {
+    let mut pairs: Vec<Vec<(f64, f64)>> = vec![Vec::new(); 12];
+    for (i, (a, b)) in target.samples.iter().zip(&neighbour.samples).enumerate() {
+        if let (Sample::Observed(y) | Sample::Interpolated(y), Sample::Observed(x) | Sample::Interpolated(x)) = (a, b) {
+            pairs[month_of_sample(target, year, i)].push((*x, *y));
+        }
+    }
+    let mut regressions = [None; 12];
+    for (month, points) in pairs.iter().enumerate() {
+        if points.len() < 48 {
+            continue; // Not enough overlap to trust a fit
+        }
+        let xs: Vec<f64> = points.iter().map(|p| p.0).collect();
+        let ys: Vec<f64> = points.iter().map(|p| p.1).collect();
+        let (mean_x, mean_y) = (stats::mean(&xs), stats::mean(&ys));
+        let covariance: f64 = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+        let variance_x: f64 = xs.iter().map(|x| (x - mean_x).powi(2)).sum();
+        let variance_y: f64 = ys.iter().map(|y| (y - mean_y).powi(2)).sum();
+        if variance_x == 0.0 || variance_y == 0.0 {
+            continue;
+        }
+        let slope = covariance / variance_x;
+        regressions[month] = Some(Regression {
+            intercept: mean_y - slope * mean_x,
+            slope,
+            correlation: covariance / (variance_x * variance_y).sqrt(),
+        });
+    }
+    regressions
+}
+
+// Fills gaps from the neighbours, most correlated first. Neighbours must be
+// resampled onto the same grid for the same year. Months where a neighbour's
+// correlation is below `min_correlation` are not used.
+pub fn fill_from_neighbours(series: &mut ResampledSeries, year: u32, neighbours: &[&ResampledSeries], min_correlation: f64) {
+    let regressions: Vec<[Option<Regression>; 12]> =
+        neighbours.iter().map(|n| monthly_regressions(series, n, year)).collect();
+    for i in 0..series.samples.len() {
+        if series.samples[i] != Sample::Missing {
+            continue;
+        }
+        let month = month_of_sample(series, year, i);
+        let best = neighbours
+            .iter()
+            .zip(&regressions)
+            .filter_map(|(neighbour, fits)| {
+                let fit = fits[month]?;
+                let x = match neighbour.samples.get(i)? {
+                    Sample::Observed(x) | Sample::Interpolated(x) => *x,
+                    _ => return None,
+                };
+                (fit.correlation >= min_correlation).then_some((fit.correlation, fit.intercept + fit.slope * x))
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0));
+        if let Some((_, value)) = best {
+            series.samples[i] = Sample::Imputed(value);
+        }
+    }
+}
+
+// Usual temperature at a sample of the series
+fn climatological_value(climatology: &HourlyClimatology, series: &ResampledSeries, index: usize) -> Option<f64> {
+    let local = series.minute_of_year(index) as i64 + climatology.utc_offset as i64;
+    if !(0..366 * 1440).contains(&local) {
+        return None;
+    }
+    let day = (local / 1440) as usize;
+    let hour = ((local % 1440) / 60) as usize;
+    climatology.smoothed(day, hour, CLIMATOLOGY_WINDOW).mean()
+}
+
+// Fills gaps with the climatology plus the anomaly of the nearest real values
+// either side, interpolated across the gap
+pub fn fill_from_climatology(series: &mut ResampledSeries, climatology: &HourlyClimatology) {
+    let anomaly_at = |series: &ResampledSeries, i: usize| -> Option<f64> {
+        let value = match series.samples[i] {
+            Sample::Observed(v) | Sample::Interpolated(v) => v,
+            _ => return None,
+        };
+        Some(value - climatological_value(climatology, series, i)?)
+    };
+    let n = series.samples.len();
+    let mut filled = Vec::new();
+    for i in 0..n {
+        if series.samples[i] != Sample::Missing {
+            continue;
+        }
+        let Some(normal) = climatological_value(climatology, series, i) else {
+            continue;
+        };
+        let before = (1..=ANOMALY_SEARCH.min(i)).find_map(|d| anomaly_at(series, i - d).map(|a| (d, a)));
+        let after = (1..=ANOMALY_SEARCH).take_while(|d| i + d < n).find_map(|d| anomaly_at(series, i + d).map(|a| (d, a)));
+        let anomaly = match (before, after) {
+            (Some((db, ab)), Some((da, aa))) => ab + (aa - ab) * db as f64 / (db + da) as f64,
+            (Some((_, a)), None) | (None, Some((_, a))) => a,
+            (None, None) => 0.0,
+        };
+        filled.push((i, normal + anomaly));
+    }
+    for (i, value) in filled {
+        series.samples[i] = Sample::Imputed(value);
+    }
+}
+
+pub fn fill_gaps(
+    series: &mut ResampledSeries,
+    year: u32,
+    strategy: Strategy,
+    climatology: Option<&HourlyClimatology>,
+    neighbours: &[&ResampledSeries],
+    min_correlation: f64,
+) {
+    if matches!(strategy, Strategy::Neighbours | Strategy::Both) {
+        fill_from_neighbours(series, year, neighbours, min_correlation);
+    }
+    if matches!(strategy, Strategy::Climatology | Strategy::Both) {
+        if let Some(climatology) = climatology {
+            fill_from_climatology(series, climatology);
+        }
+    }
+}
+
+pub fn coverage(series: &ResampledSeries) -> Coverage {
+    let mut coverage = Coverage::default();
+    for sample in &series.samples {
+        let bucket = match sample {
+            Sample::Observed(_) => &mut coverage.observed,
+            Sample::Interpolated(_) => &mut coverage.interpolated,
+            Sample::Imputed(_) => &mut coverage.imputed,
+            Sample::Missing => &mut coverage.missing,
+        };
+        *bucket += series.interval;
+    }
+    coverage
+}
+
+// Turns the series back into TempData so the existing daily aggregates can run
+// on it. Missing points are left out.
+pub fn to_temp_data(series: &ResampledSeries) -> Vec<TempData> {
+    series
+        .samples
+        .iter()
+        .enumerate()
+        .filter_map(|(i, sample)| {
+            Some(TempData {
+                temp10: Tenths::from_degrees(sample.value()?),
+                duration: series.interval as u16,
+                minute_of_year: series.minute_of_year(i),
+            })
+        })
+        .collect()
+}
+
+pub fn print_coverage(station: &str, coverage: &Coverage) {
+    let total = (coverage.observed + coverage.interpolated + coverage.imputed + coverage.missing).max(1) as f64;
+    let percent = |minutes: u32| minutes as f64 * 100.0 / total;
+    println!(
+        "Coverage at {}: {:.1}% observed, {:.1}% interpolated, {:.1}% imputed, {:.1}% missing",
+        station,
+        percent(coverage.observed),
+        percent(coverage.interpolated),
+        percent(coverage.imputed),
+        percent(coverage.missing)
+    );
+}
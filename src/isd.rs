@@ -0,0 +1,292 @@
+use crate::daily;
+use crate::units::{Celsius, Tenths};
+
+/*
+Helpers for reading the parts of an ISD record beyond the air temperature.
+Every record has a fixed width control section (0..60) and mandatory data
+section (60..105). Anything after that is optional: "ADD" followed by a
+sequence of groups, each starting with a three character identifier such as
+"KA1" or "AA1", then possibly "REM" remarks and "EQD" element quality data.
+See https://www.ncei.noaa.gov/data/global-hourly/doc/isd-format-document.pdf
+*/
+
+// Minute of the year at which the record was observed (UTC)
+pub fn minute_of_year(line: &str) -> u32 {
+    let year: u32 = line[15..19].parse().unwrap();
+    let day_of_year = crate::get_day_of_year(&line[19..23], daily::is_leap_year(year));
+    let hours: u32 = line[23..25].parse().unwrap();
+    let minutes: u32 = line[25..27].parse().unwrap();
+    (day_of_year as u32 - 1) * 1440 + hours * 60 + minutes
+}
+
+// The additional data section of a record, without the "ADD" marker and
+// without any remarks or element quality data which follow it.
+fn additional_section(line: &str) -> Option<&str> {
+    if line.get(105..108) != Some("ADD") {
+        return None;
+    }
+    let section = &line[108..];
+    let end = ["REM", "EQD", "QNN"].iter().filter_map(|marker| section.find(marker)).min().unwrap_or(section.len());
+    Some(&section[..end])
+}
+
+// Returns the `len` characters of data which follow the group identifier `id`
+// (eg. "KA1"), or None if the record does not contain the group.
+pub fn find_group<'a>(line: &'a str, id: &str, len: usize) -> Option<&'a str> {
+    let section = additional_section(line)?;
+    let start = section.find(id)? + id.len();
+    section.get(start..start + len)
+}
+
+// ISD quality codes which mean the value passed quality control (or wasn't
+// checked, which is all we get for some of the additional groups).
+pub fn passed_quality_control(code: &str) -> bool {
+    matches!(code, "0" | "1" | "4" | "5" | "9" | "A" | "C" | "I" | "M" | "P" | "R" | "U")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExtremeKind {
+    Max,
+    Min,
+}
+
+// An extreme air temperature group (KA1-KA4): the highest or lowest
+// temperature over the period ending at the time of the observation.
+#[derive(Clone, Copy, Debug)]
+pub struct ExtremeTemp {
+    pub kind: ExtremeKind,
+    pub temp10: Tenths<Celsius>,
+    pub minute_of_year: u32, // End of the period
+    pub period: u32, // Length of the period in minutes
+}
+
+// Extracts every valid KA1-KA4 group from the records
+pub fn extract_extreme_temps(raw_data: &[String]) -> Vec<ExtremeTemp> {
+    let mut extremes = Vec::new();
+    for line in raw_data {
+        for id in ["KA1", "KA2", "KA3", "KA4"] {
+            // Period (hours * 10), code, temperature (°C * 10), quality
+            let Some(group) = find_group(line, id, 10) else {
+                continue;
+            };
+            let kind = match &group[3..4] {
+                "M" => ExtremeKind::Max,
+                "N" => ExtremeKind::Min,
+                _ => continue, // Estimated or unknown
+            };
+            let (Ok(period10), Ok(temp10)) = (group[0..3].parse::<u32>(), group[4..9].parse::<i16>()) else {
+                continue;
+            };
+            if period10 == 999 || temp10 == 9999 || !passed_quality_control(&group[9..10]) {
+                continue;
+            }
+            extremes.push(ExtremeTemp { kind, temp10: Tenths::new(temp10), minute_of_year: minute_of_year(line), period: period10 * 6 });
+        }
+    }
+    extremes
+}
+
+// A liquid precipitation group (AA1-AA4): the depth which fell over the
+// period ending at the time of the observation. Different report types cover
+// different periods (1, 3, 6 or 24 hours), so groups from nearby records
+// often overlap. Only groups whose condition code says the depth covers just
+// their own period are kept; see extract_precipitation.
+#[derive(Clone, Copy, Debug)]
+pub struct Precipitation {
+    pub minute_of_year: u32, // End of the period
+    pub period: u32, // Length of the period in minutes
+    pub depth: f64, // mm, a trace counts as zero
+}
+
+// Extracts every valid AA1-AA4 group from the records
+pub fn extract_precipitation(raw_data: &[String]) -> Vec<Precipitation> {
+    let mut precipitation = Vec::new();
+    for line in raw_data {
+        for id in ["AA1", "AA2", "AA3", "AA4"] {
+            // Period (hours), depth (mm * 10), condition, quality
+            let Some(group) = find_group(line, id, 8) else {
+                continue;
+            };
+            let (Ok(hours), Ok(depth10)) = (group[0..2].parse::<u32>(), group[2..6].parse::<u32>()) else {
+                continue;
+            };
+            if hours == 0 || hours == 99 || depth10 == 9999 || !passed_quality_control(&group[7..8]) {
+                continue;
+            }
+            // Condition: 2 is a trace, 9 no condition given and E an
+            // estimate. 1 (measurement impossible), 5-8 (deleted or missing
+            // periods) and I/J (incomplete) don't hold the whole depth. 3 and 4
+            // begin and end an accumulation, whose depth is everything since
+            // the start rather than over the group's period, so it would be
+            // counted again on top of the reports inside it.
+            let depth = match &group[6..7] {
+                "2" => 0.0,
+                "9" | "E" => depth10 as f64 / 10.0,
+                _ => continue,
+            };
+            precipitation.push(Precipitation { minute_of_year: minute_of_year(line), period: hours * 60, depth });
+        }
+    }
+    precipitation
+}
+
+// Where the station says it is, from the control section of a record
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Location {
+    pub latitude: f64, // degrees
+    pub longitude: f64, // degrees
+    pub elevation: i32, // metres
+}
+
+pub fn location(line: &str) -> Option<Location> {
+    let latitude: i32 = line.get(28..34)?.parse().ok()?;
+    let longitude: i32 = line.get(34..41)?.parse().ok()?;
+    let elevation: i32 = line.get(46..51)?.parse().ok()?;
+    if latitude == 99999 || longitude == 999999 || elevation == 9999 {
+        return None;
+    }
+    Some(Location { latitude: latitude as f64 / 1000.0, longitude: longitude as f64 / 1000.0, elevation })
+}
+
+// A change in the reported location of the station
+#[derive(Clone, Copy, Debug)]
+pub struct LocationChange {
+    pub time: f64, // Fractional year of the first month at the new location
+    pub from: Location,
+    pub to: Location,
+}
+
+// Finds the months where the station's reported location changed. Different
+// report types from the same station often disagree slightly on its position,
+// so each month is represented by the location most of its records report.
+// `raw_years[0]` holds the records for `first_year`.
+pub fn location_changes(raw_years: &[Vec<String>], first_year: u32) -> Vec<LocationChange> {
+    let mut changes = Vec::new();
+    let mut previous: Option<Location> = None;
+    for (i, records) in raw_years.iter().enumerate() {
+        let year = first_year + i as u32;
+        let mut months: Vec<Vec<(Location, usize)>> = vec![Vec::new(); 12];
+        for line in records {
+            let (Some(location), Ok(month)) = (location(line), line[19..21].parse::<usize>()) else {
+                continue;
+            };
+            let counts = &mut months[month - 1];
+            match counts.iter_mut().find(|(l, _)| *l == location) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((location, 1)),
+            }
+        }
+        for (month, counts) in months.iter().enumerate() {
+            let Some(&(mode, _)) = counts.iter().max_by_key(|(_, count)| *count) else {
+                continue;
+            };
+            if let Some(from) = previous {
+                if from != mode {
+                    changes.push(LocationChange { time: year as f64 + month as f64 / 12.0, from, to: mode });
+                }
+            }
+            previous = Some(mode);
+        }
+    }
+    changes
+}
+
+// Wind from the mandatory data section
+#[derive(Clone, Copy, Debug)]
+pub struct Wind {
+    pub minute_of_year: u32,
+    pub direction: Option<u16>, // Degrees from true north the wind blows from, None when calm or variable
+    pub speed: f64, // m/s
+    pub calm: bool,
+}
+
+pub fn wind(line: &str) -> Option<Wind> {
+    // Direction (degrees), quality, type code, speed (m/s * 10), quality
+    let direction: u16 = line.get(60..63)?.parse().ok()?;
+    let speed10: u16 = line.get(65..69)?.parse().ok()?;
+    let kind = line.get(64..65)?;
+    if speed10 == 9999 || kind == "9" || !passed_quality_control(line.get(69..70)?) {
+        return None;
+    }
+    let calm = kind == "C" || speed10 == 0;
+    let direction = if calm || direction == 999 || kind == "V" || !passed_quality_control(line.get(63..64)?) {
+        None
+    } else {
+        Some(direction % 360)
+    };
+    Some(Wind { minute_of_year: minute_of_year(line), direction, speed: speed10 as f64 / 10.0, calm })
+}
+
+// Peak gust (OC1) in m/s
+pub fn gust(line: &str) -> Option<f64> {
+    // Speed (m/s * 10), quality
+    let group = find_group(line, "OC1", 5)?;
+    let speed10: u16 = group[0..4].parse().ok()?;
+    if speed10 == 9999 || !passed_quality_control(&group[4..5]) {
+        return None;
+    }
+    Some(speed10 as f64 / 10.0)
+}
+
+// Air temperature from the mandatory data section, for records which pass
+// the same checks as the main air temperature series (see rejection_reason)
+pub fn air_temperature(line: &str) -> Option<Tenths<Celsius>> {
+    if crate::rejection_reason(line).is_some() {
+        return None;
+    }
+    Some(Tenths::new(line.get(87..92)?.parse().ok()?))
+}
+
+// Dew point from the mandatory data section, next to the air temperature.
+// Held to the same standard as the air temperature, so that anything derived
+// from the two only uses records the main series keeps.
+pub fn dew_point(line: &str) -> Option<Tenths<Celsius>> {
+    if crate::rejection_reason(line).is_some() || line.get(98..99)? != "5" {
+        return None;
+    }
+    let temp10: i16 = line.get(93..98)?.parse().ok()?;
+    if temp10 == 9999 {
+        return None;
+    }
+    Some(Tenths::new(temp10))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A record with only the fields these tests look at filled in
+    fn record(process: &str, temp: &str, dew_point: &str) -> String {
+        let mut line = "0".repeat(105);
+        line.replace_range(15..27, "202201011200");
+        line.replace_range(56..59, process);
+        line.replace_range(87..93, temp);
+        line.replace_range(93..99, dew_point);
+        line
+    }
+
+    #[test]
+    fn dew_point_uses_the_air_temperature_checks() {
+        let good = record("V02", "+02505", "+01005");
+        assert_eq!(air_temperature(&good).map(|t| t.tenths()), Some(250));
+        assert_eq!(dew_point(&good).map(|t| t.tenths()), Some(100));
+        // Rejected for the air temperature, so no dew point either
+        for line in [record("V01", "+02505", "+01005"), record("V02", "+02501", "+01005"), record("V02", "+99999", "+01005")] {
+            assert!(air_temperature(&line).is_none());
+            assert!(dew_point(&line).is_none());
+        }
+        assert!(dew_point(&record("V02", "+02505", "+01001")).is_none());
+        assert!(dew_point(&record("V02", "+02505", "+99995")).is_none());
+    }
+
+    #[test]
+    fn precipitation_keeps_traces_and_skips_accumulations() {
+        let line = record("V02", "+02505", "+01005") + "ADDAA101002021AA206015045AA324010091AA403012011";
+        let groups = extract_precipitation(&[line]);
+        let depths: Vec<(u32, f64)> = groups.iter().map(|p| (p.period / 60, p.depth)).collect();
+        // The trace counts as zero, the end of an accumulation and the
+        // measurement marked impossible are left out
+        assert_eq!(depths, vec![(1, 0.0), (24, 10.0)]);
+        assert!(groups.iter().all(|p| p.minute_of_year == 12 * 60));
+    }
+}
@@ -1,6 +1,34 @@
-use std::{fs, io::Read};
+use std::{fs, io::Read, io::Write};
 use flate2::read::GzDecoder;
-use plotters::prelude::*;
+use units::{Celsius, Tenths};
+
+mod animation;
+mod changepoint;
+mod chart;
+mod coverage;
+mod daily;
+mod degree_days;
+mod distribution;
+mod diurnal;
+mod events;
+mod export;
+mod extremes;
+mod heatmap;
+mod humidity;
+mod impute;
+mod isd;
+mod normals;
+mod precipitation;
+mod resample;
+mod spectral;
+mod stats;
+mod stl;
+mod strangeness;
+mod stripes;
+mod trend;
+mod units;
+mod variability;
+mod wind;
 
 /*  
 SWEAT - Strange WEather in AusTin
@@ -21,33 +49,576 @@ fn main() {
     */
     // let token = fs::read_to_string("./token")
     //     .expect("Could not read token form file");
-    let year = "2022";
+
+    // Usage: sweat [command] [--option value]...
+    // Without a command this prints the daily averages for 2022, as it always
+    // has. The commands are listed in YEAR_COMMANDS and HISTORY_COMMANDS, and
+    // the choices for each option where option() is called for it.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = args.first().filter(|arg| !arg.starts_with("--")).map_or("daily", String::as_str);
+    // Statistics, exports and charts
+    units::set_unit(option(&args, "--unit", &[
+        ("celsius", units::Unit::Celsius),
+        ("fahrenheit", units::Unit::Fahrenheit),
+        ("kelvin", units::Unit::Kelvin),
+    ]));
     // camp mabry : 13958
     // albany: 14735
     // san juan: 11641
-    let wbans = ["13958"/*, "23188"*/]; // TODO: WBAN #. Hard coded for now.
+    let wbans: Vec<&str> = value(&args, "--stations").map_or(vec!["13958"], |list| list.split(',').collect());
+    if HISTORY_COMMANDS.contains(&command) {
+        let years = value(&args, "--years").map_or(1993..=2022, parse_range);
+        run_history_command(command, &wbans, years, &args);
+        return;
+    }
+    if !YEAR_COMMANDS.contains(&command) {
+        panic!("Unknown command {}, expected one of: {}, {}", command, YEAR_COMMANDS.join(", "), HISTORY_COMMANDS.join(", "));
+    }
+
+    let year: u32 = value(&args, "--year").map_or(2022, |year| year.parse().expect("--year must be a number"));
     // let mut location_temps: Vec<Vec<Vec<i16>>> = Vec::new();
     let mut location_temps: Vec<Vec<TempData>> = Vec::new();
-    for wban in wbans {
-        let data = download_data(year, wban, true);
+    for wban in &wbans {
+        let data = download_data(&year.to_string(), wban, true);
         if let Err(error) = data {
             panic!("Could not download data: {}", error.to_string());
         }
         let mut data = data.unwrap();
         data.pop(); // Last record is always empty
+        // Coverage, precipitation, humidity and wind need the records which
+        // remove_invalid_entries drops for their temperature
+        let raw = data.clone();
         remove_invalid_entries(&mut data);
         let mut temperatures = extract_detailed_temps(&data);
-        println!("Num temps: {}", temperatures.len());
+        if command == "daily" {
+            println!("Num temps: {}", temperatures.len());
+        }
+        run_station_command(command, wban, year, &raw, &data, &temperatures, &args);
         // remove_past_day(&mut temperatures, 7);
         // combine_like_temps(&mut temperatures);
-        // plot_detailed_week_dist(&temperatures);
         // let daily_temps = extract_temps(&data, true);
         location_temps.push(temperatures);
     }
-    calc_daily_average(&location_temps);
+
+    let stations: Vec<(&str, &[TempData])> =
+        wbans.iter().zip(&location_temps).map(|(wban, temps)| (station_name(wban), &temps[..])).collect();
+    match command {
+        "daily" => calc_daily_average(&location_temps),
+        "export" => {
+            let json = option(&args, "--format", &[("csv", false), ("json", true)]);
+            for (wban, temps) in wbans.iter().zip(&location_temps) {
+                match json {
+                    false => print!("{}", export::daily_csv(std::slice::from_ref(temps), year)),
+                    true => println!("{}", export::daily_json(station_name(wban), std::slice::from_ref(temps), year)),
+                }
+            }
+        }
+        "resample" => {
+            let method = option(&args, "--resample", &[
+                ("linear", resample::Method::Linear),
+                ("step", resample::Method::StepHold),
+                ("nearest", resample::Method::Nearest),
+            ]);
+            let interval = number(&args, "--interval").map_or(60, |minutes| minutes as u32);
+            let max_gap = number(&args, "--max-gap").map_or(180, |minutes| minutes as u32);
+            println!("station,minute,temperature,source");
+            for (wban, temps) in wbans.iter().zip(&location_temps) {
+                let series = resample::resample(temps, year, interval, method, max_gap).expect("--interval must be more than 0");
+                for (i, sample) in series.samples.iter().enumerate() {
+                    let source = match sample {
+                        resample::Sample::Observed(_) => "observed",
+                        resample::Sample::Interpolated(_) => "interpolated",
+                        resample::Sample::Imputed(_) => "imputed",
+                        resample::Sample::Missing => "missing",
+                    };
+                    let value = sample.value().map_or_else(String::new, |v| format!("{:.1}", units::temperature(v)));
+                    println!("{},{},{},{}", wban, series.minute_of_year(i), value, source);
+                }
+            }
+        }
+        "overlay" => {
+            let mode = option(&args, "--mode", &[("anomaly", chart::OverlayMode::Anomaly), ("raw", chart::OverlayMode::Raw)]);
+            output_chart(chart::ChartSpec::overlay(&stations, 0, 527040, mode), "overlay.png", &args);
+        }
+        "animate" => {
+            let mode = option(&args, "--animation", &[
+                ("distribution", animation::AnimationMode::Distribution),
+                ("scatter", animation::AnimationMode::Scatter),
+            ]);
+            let mut settings = animation::AnimationSettings::new(mode);
+            settings.step = number(&args, "--step").map_or(1440, |days| (days * 1440.0).round() as u32);
+            if let Some(days) = number(&args, "--window") {
+                settings.window = (days * 1440.0).round() as u32;
+            }
+            if let Some(fps) = number(&args, "--fps") {
+                settings.frames_per_second = fps as u32;
+            }
+            animation::animate(&stations, year, &settings, &image_path(&format!("{}.gif", year))).unwrap();
+        }
+        _ => (),
+    }
     // process_temps(&location_temps)
 }
 
+// Commands on a single year, for every station given
+const YEAR_COMMANDS: &[&str] = &[
+    "daily",
+    "coverage",
+    "variability",
+    "resample",
+    "temperatures",
+    "export",
+    "overlay",
+    "distribution",
+    "animate",
+    "wind",
+    "humidity",
+    "precipitation",
+];
+
+// Runs the single year commands which look at one station at a time. `raw` is
+// every record, `data` only the ones which passed remove_invalid_entries.
+fn run_station_command(command: &str, wban: &str, year: u32, raw: &[String], data: &[String], temperatures: &[TempData], args: &[String]) {
+    let name = station_name(wban);
+    match command {
+        "coverage" => {
+            let report = coverage::year_coverage(raw, year, 0.75);
+            coverage::plot_coverage_calendar(&report, &image_path(&format!("coverage_{}.png", wban))).unwrap();
+            coverage::print_coverage_table(name, &[report]);
+        }
+        "variability" => {
+            let extreme_temps = isd::extract_extreme_temps(data);
+            let report = variability::variability_report(temperatures, &extreme_temps, station_utc_offset(wban), 5.0);
+            variability::print_variability_report(name, year, &report);
+        }
+        "distribution" => {
+            let mut distribution = chart::ChartSpec::distribution(temperatures);
+            distribution.add_density(kernel_option(args), bandwidth_option(args));
+            output_chart(distribution, &format!("distribution_{}.png", wban), args);
+        }
+        "precipitation" => {
+            let rain = precipitation::daily_totals(&isd::extract_precipitation(raw), year, 0.9);
+            precipitation::print_monthly_totals(name, year, &[precipitation::monthly_totals(year, &rain, precipitation::MONTH_COVERAGE)]);
+            precipitation::print_precipitation_stats(name, &precipitation::precipitation_stats(&[rain], year));
+        }
+        "humidity" => {
+            let humid = humidity::extract_observations(raw);
+            humidity::print_monthly_humidity(name, year, &humidity::monthly_humidity(year, &humid));
+            let (variable, title) = option(args, "--derived", &[
+                ("heat-index", (humidity::DerivedTemperature::HeatIndex, "Heat index")),
+                ("wind-chill", (humidity::DerivedTemperature::WindChill, "Wind chill")),
+                ("apparent", (humidity::DerivedTemperature::ApparentTemperature, "Apparent temperature")),
+                ("dew-point", (humidity::DerivedTemperature::DewPoint, "Dew point")),
+            ]);
+            let mut spec = chart::ChartSpec::temperatures(&humidity::derived_temps(&humid, variable), 0, 527040);
+            spec.title = Some(format!("{} at {} in {}", title, name, year));
+            output_chart(spec, &format!("derived_{}.png", wban), args);
+        }
+        "wind" => {
+            let winds = wind::extract_wind(raw);
+            wind::print_wind_stats(name, &wind::wind_stats(&winds));
+            let rose = wind::wind_rose(&winds, 16, wind::CALM_BELOW, &wind::SPEED_CLASSES);
+            wind::print_wind_rose(name, &rose);
+            chart::to_file(&chart::WindRoseChart::new(rose), &image_path(&format!("wind_rose_{}.png", wban))).unwrap();
+        }
+        "temperatures" => {
+            // Days of the year, eg. 0-6 for the first week
+            let days = value(args, "--days").map_or(0..=365, parse_range);
+            let spec = chart::ChartSpec::temperatures(temperatures, days.start() * 1440, (days.end() + 1) * 1440);
+            output_chart(spec, &format!("temperatures_{}.png", wban), args);
+        }
+        _ => (),
+    }
+}
+
+// Commands on every year in --years, for every station given
+const HISTORY_COMMANDS: &[&str] = &[
+    "extremes",
+    "events",
+    "degree-days",
+    "diurnal",
+    "trend",
+    "breakpoints",
+    "decompose",
+    "compare",
+    "impute",
+    "normals",
+    "anomalies",
+    "stripes",
+    "spread",
+];
+
+// Runs the commands which need many years of data. The last year is the one
+// being judged where a command compares a year against the others.
+fn run_history_command(command: &str, wbans: &[&str], years: std::ops::RangeInclusive<u32>, args: &[String]) {
+    let (first_year, last_year) = (*years.start(), *years.end());
+    let last = (last_year - first_year) as usize;
+    // Only station moves and the metrics beyond temperature need the records
+    // which remove_invalid_entries drops
+    let raw_years: Vec<Vec<Vec<String>>> = match command {
+        "breakpoints" | "compare" => wbans.iter().map(|wban| download_raw_years(wban, years.clone()).unwrap()).collect(),
+        _ => Vec::new(),
+    };
+    let station_years: Vec<Vec<Vec<TempData>>> = if raw_years.is_empty() {
+        wbans.iter().map(|wban| download_years(wban, years.clone()).unwrap()).collect()
+    } else {
+        raw_years.iter().map(|raw| valid_years(raw.clone())).collect()
+    };
+
+    match command {
+        "degree-days" => {
+            let method = option(args, "--method", &[
+                ("integrated", degree_days::DegreeDayMethod::Integrated),
+                ("average", degree_days::DegreeDayMethod::DailyAverage),
+            ]);
+            let base = temperature(args, "--base").unwrap_or(degree_days::DEFAULT_BASE);
+            let rows: Vec<_> = wbans
+                .iter()
+                .zip(&station_years)
+                .map(|(wban, years)| {
+                    let by_year = degree_days::monthly_totals_by_year(years, first_year, base, method);
+                    (station_name(wban), by_year[last], Some(degree_days::climatology(&by_year[..last], first_year)))
+                })
+                .collect();
+            degree_days::print_degree_days(&rows, base);
+        }
+        "impute" => {
+            // Fill the gaps in the last year at the first station from the
+            // climatology of the other years, or from the other stations
+            let method = option(args, "--resample", &[
+                ("linear", resample::Method::Linear),
+                ("step", resample::Method::StepHold),
+                ("nearest", resample::Method::Nearest),
+            ]);
+            let strategy = option(args, "--strategy", &[
+                ("climatology", impute::Strategy::Climatology),
+                ("neighbours", impute::Strategy::Neighbours),
+                ("both", impute::Strategy::Both),
+            ]);
+            let climatology = diurnal::hourly_climatology(&station_years[0][..last], station_utc_offset(wbans[0]));
+            let neighbours: Vec<resample::ResampledSeries> =
+                station_years[1..].iter().filter_map(|years| resample::resample(&years[last], last_year, 60, method, 180)).collect();
+            let mut hourly = resample::resample(&station_years[0][last], last_year, 60, method, 180).unwrap();
+            impute::fill_gaps(&mut hourly, last_year, strategy, Some(&climatology), &neighbours.iter().collect::<Vec<_>>(), 0.8);
+            impute::print_coverage(station_name(wbans[0]), &impute::coverage(&hourly));
+            let mut spec = chart::ChartSpec::temperatures(&impute::to_temp_data(&hourly), 0, 527040);
+            spec.title = Some(format!("{} in {} with the gaps filled", station_name(wbans[0]), last_year));
+            output_chart(spec, &format!("filled_{}.png", wbans[0]), args);
+        }
+        "spread" => {
+            let (kernel, rule) = (kernel_option(args), bandwidth_option(args));
+            let spread = distribution::MonthlyBoxViolin::new(
+                wbans
+                    .iter()
+                    .zip(&station_years)
+                    .map(|(wban, years)| (station_name(wban).to_string(), distribution::monthly_distributions(years, first_year, kernel, rule)))
+                    .collect(),
+            );
+            output_chart(spread.spec(), "monthly_spread.png", args);
+        }
+        "compare" => {
+            // The weather noise left once the seasons and trend are removed,
+            // also of the dew point and derived temperatures, how its
+            // variance is spread over periods, the last year's
+            // humidity and wind, and the precipitation of every year
+            let mut stations = Vec::new();
+            for ((wban, years), raw) in wbans.iter().zip(&station_years).zip(&raw_years) {
+                let noise = stl::decompose(years, first_year, &stl::StlParameters::default());
+                let spectrum = spectral::spectral_report(years, first_year, station_utc_offset(wban), 30);
+                spectral::print_spectral_report(station_name(wban), &spectrum);
+                let rain: Vec<Vec<Option<f64>>> = raw
+                    .iter()
+                    .zip(first_year..)
+                    .map(|(records, year)| precipitation::daily_totals(&isd::extract_precipitation(records), year, 0.9))
+                    .collect();
+                let observations: Vec<Vec<humidity::Observation>> = raw.iter().map(|records| humidity::extract_observations(records)).collect();
+                let metrics = [
+                    strangeness::noise_metrics(&noise.remainder),
+                    strangeness::derived_noise_metrics(&observations, first_year),
+                    spectral::spectral_metrics(&spectrum),
+                    strangeness::humidity_metrics(&observations[last]),
+                    strangeness::wind_metrics(&wind::wind_stats(&wind::extract_wind(&raw[last]))),
+                    strangeness::precipitation_metrics(&precipitation::precipitation_stats(&rain, first_year)),
+                ]
+                .concat();
+                stations.push((station_name(wban), metrics));
+            }
+            match option(args, "--format", &[("table", None), ("csv", Some(false)), ("json", Some(true))]) {
+                None => strangeness::print_comparison(&stations),
+                Some(false) => print!("{}", export::metrics_csv(&stations)),
+                Some(true) => println!("{}", export::metrics_json(&stations)),
+            }
+        }
+        "trend" => {
+            let (aggregate, description) = option(args, "--aggregate", &[
+                ("monthly", (trend::Aggregate::MonthlyAnomaly, "monthly anomalies")),
+                ("annual", (trend::Aggregate::Annual, "annual means")),
+                ("winter", (trend::Aggregate::Seasonal(trend::Season::Winter), "winter means")),
+                ("spring", (trend::Aggregate::Seasonal(trend::Season::Spring), "spring means")),
+                ("summer", (trend::Aggregate::Seasonal(trend::Season::Summer), "summer means")),
+                ("autumn", (trend::Aggregate::Seasonal(trend::Season::Autumn), "autumn means")),
+            ]);
+            let trends: Vec<(&str, trend::TrendResult)> = wbans
+                .iter()
+                .zip(&station_years)
+                .filter_map(|(wban, years)| {
+                    let series = trend::aggregate_series(years, first_year, aggregate, 0.8);
+                    Some((station_name(wban), trend::mann_kendall(&series, true, 0.95)?))
+                })
+                .collect();
+            for (name, result) in &trends {
+                trend::print_trend(name, description, result);
+            }
+            if let [a, b] = &trends[..] {
+                trend::print_trend_comparison((a.0, &a.1), (b.0, &b.1));
+            }
+        }
+        _ => {
+            for (i, (wban, years)) in wbans.iter().zip(&station_years).enumerate() {
+                run_station_history_command(command, wban, years, first_year, raw_years.get(i), args);
+            }
+        }
+    }
+}
+
+// Runs the history commands which look at one station at a time
+fn run_station_history_command(
+    command: &str,
+    wban: &str,
+    station_years: &[Vec<TempData>],
+    first_year: u32,
+    raw_years: Option<&Vec<Vec<String>>>,
+    args: &[String],
+) {
+    let name = station_name(wban);
+    let last = station_years.len() - 1;
+    let last_year = first_year + last as u32;
+    match command {
+        "extremes" => {
+            let method = option(args, "--fit", &[
+                ("mle", extremes::FitMethod::MaximumLikelihood),
+                ("l-moments", extremes::FitMethod::LMoments),
+            ]);
+            let maxima = extremes::annual_maxima(station_years);
+            let observed = maxima.last().copied();
+            match extremes::fit_gev(&maxima, method) {
+                Some(fit) => extremes::print_extremes_report(name, &fit, observed),
+                None => println!("Could not fit a GEV to the annual maxima at {}", name),
+            }
+            // Peaks over the 98th percentile of the daily maxima by default
+            let daily_maxima: Vec<f64> =
+                station_years.iter().flat_map(|temps| daily::daily_stats(temps)).flatten().map(|s| s.get(daily::DailyVariable::Max)).collect();
+            let threshold = temperature(args, "--threshold").unwrap_or_else(|| stats::quantile(&daily_maxima, 0.98));
+            let peaks = extremes::peaks_over_threshold(station_years, threshold, 3);
+            match extremes::fit_gpd(&peaks, threshold, station_years.len(), method) {
+                Some(fit) => extremes::print_extremes_report(name, &fit, observed),
+                None => println!("Could not fit a GPD to the peaks over {:.1} {} at {}", units::temperature(threshold), units::symbol(), name),
+            }
+        }
+        "events" => {
+            events::print_events(name, "Heat wave", &events::detect_events(station_years, first_year, &events::HEAT_WAVE));
+            events::print_events(name, "Cold snap", &events::detect_events(station_years, first_year, &events::COLD_SNAP));
+            events::print_freeze_dates(name, &events::freeze_dates(station_years, first_year));
+        }
+        "diurnal" => {
+            let climatology = diurnal::hourly_climatology(station_years, station_utc_offset(wban));
+            diurnal::print_monthly_cycles(name, &climatology);
+            // Days either side pooled into the usual shape, and the RMS
+            // z-score of the departures from it which counts as abnormal
+            let window = number(args, "--window").map_or(7, |days| days as usize);
+            let threshold = number(args, "--threshold").unwrap_or(2.0);
+            let abnormal = diurnal::abnormal_days(&station_years[last], &climatology, window, threshold);
+            diurnal::print_abnormal_days(name, last_year, &abnormal);
+        }
+        "normals" => {
+            let variable = variable_option(args);
+            let normals = normals::normals(&station_years[..last], first_year, variable, 7);
+            let climatology = chart::ChartSpec::climatology(&normals, &station_years[last], last_year, variable);
+            output_chart(climatology, &format!("climatology_{}.png", wban), args);
+        }
+        "anomalies" => {
+            let variable = variable_option(args);
+            let normals = normals::normals(&station_years[..last], first_year, variable, 7);
+            // Purple to orange for readers who can't tell red from green
+            let colour_map = option(args, "--colours", &[("blue-red", chart::BLUE_WHITE_RED), ("purple-orange", chart::PURPLE_ORANGE)]);
+            let anomalies = heatmap::daily_anomalies(station_years, first_year, &normals, variable);
+            let mut calendar = heatmap::AnomalyCalendar::new(last_year, anomalies[last].clone(), 6.0);
+            calendar.colour_map = colour_map;
+            chart::to_file(&calendar.spec(), &image_path(&format!("anomalies_{}_{}.svg", last_year, wban))).unwrap();
+            let mut heatmap = heatmap::AnomalyHeatmap::new(first_year, anomalies, 6.0);
+            heatmap.colour_map = colour_map;
+            chart::to_file(&heatmap.spec(), &image_path(&format!("anomalies_{}.png", wban))).unwrap();
+        }
+        "stripes" => {
+            let colour_map = option(args, "--colours", &[
+                ("stripes", chart::STRIPES),
+                ("blue-red", chart::BLUE_WHITE_RED),
+                ("purple-orange", chart::PURPLE_ORANGE),
+            ]);
+            let annual = trend::aggregate_series(station_years, first_year, trend::Aggregate::Annual, 0.8);
+            let mut warming = stripes::WarmingStripes::new(&annual);
+            warming.colour_map = colour_map;
+            chart::to_file(&warming.spec(), &image_path(&format!("stripes_{}.svg", wban))).unwrap();
+            let mut bars = stripes::AnomalyBars::new(&annual);
+            bars.colour_map = colour_map;
+            chart::to_file(&bars.spec(), &image_path(&format!("annual_anomalies_{}.png", wban))).unwrap();
+        }
+        "decompose" => {
+            let decomposition = stl::decompose(station_years, first_year, &stl::StlParameters::default());
+            let mut spec = stl::decomposition_chart(&decomposition);
+            spec.title = Some(format!("{} daily means, {}-{}", name, first_year, last_year));
+            output_chart(spec, &format!("decomposition_{}.png", wban), args);
+        }
+        "breakpoints" => {
+            // Station moves and instrument changes show up as breakpoints in the anomalies
+            let method = option(args, "--method", &[
+                ("pelt", changepoint::Method::Pelt),
+                ("binary-segmentation", changepoint::Method::BinarySegmentation),
+            ]);
+            let anomalies = trend::aggregate_series(station_years, first_year, trend::Aggregate::MonthlyAnomaly, 0.8);
+            let penalty = changepoint::default_penalty(anomalies.len());
+            let breakpoints = changepoint::detect_breakpoints(&anomalies, method, penalty, 12);
+            let changes = raw_years.map_or_else(Vec::new, |raw| isd::location_changes(raw, first_year));
+            let matched = changepoint::cross_reference(&breakpoints, &changes, 1.0);
+            changepoint::print_breakpoints(name, &matched, &changes, changepoint::snht(&anomalies).as_ref());
+        }
+        _ => (),
+    }
+}
+
+// Value following `name` on the command line, eg. "2021" for --year 2021
+fn value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.windows(2).find(|pair| pair[0] == name).map(|pair| pair[1].as_str())
+}
+
+fn number(args: &[String], name: &str) -> Option<f64> {
+    value(args, name).map(|number| number.parse().unwrap_or_else(|_| panic!("{} must be a number", name)))
+}
+
+// A temperature given in the --unit chosen, in °C
+fn temperature(args: &[String], name: &str) -> Option<f64> {
+    number(args, name).map(units::from_display)
+}
+
+// The choice named after `name` on the command line, or the first choice if
+// the option isn't given
+fn option<T: Copy>(args: &[String], name: &str, choices: &[(&str, T)]) -> T {
+    let Some(given) = value(args, name) else {
+        return choices[0].1;
+    };
+    match choices.iter().find(|(choice, _)| *choice == given) {
+        Some((_, choice)) => *choice,
+        None => {
+            let names: Vec<&str> = choices.iter().map(|(choice, _)| *choice).collect();
+            panic!("Unknown {} {}, expected one of: {}", name, given, names.join(", "))
+        }
+    }
+}
+
+fn kernel_option(args: &[String]) -> stats::Kernel {
+    option(args, "--kernel", &[("gaussian", stats::Kernel::Gaussian), ("epanechnikov", stats::Kernel::Epanechnikov)])
+}
+
+// A rule, or a fixed bandwidth in the --unit chosen
+fn bandwidth_option(args: &[String]) -> stats::Bandwidth {
+    match value(args, "--bandwidth").and_then(|width| width.parse().ok()) {
+        Some(width) => stats::Bandwidth::Fixed(units::difference_from_display(width)),
+        None => option(args, "--bandwidth", &[("silverman", stats::Bandwidth::Silverman), ("scott", stats::Bandwidth::Scott)]),
+    }
+}
+
+fn variable_option(args: &[String]) -> daily::DailyVariable {
+    option(args, "--variable", &[
+        ("mean", daily::DailyVariable::Mean),
+        ("max", daily::DailyVariable::Max),
+        ("min", daily::DailyVariable::Min),
+    ])
+}
+
+// "1993-2022", or a single number
+fn parse_range(range: &str) -> std::ops::RangeInclusive<u32> {
+    let parse = |number: &str| number.parse::<u32>().unwrap_or_else(|_| panic!("Expected a range like 1993-2022, got {}", range));
+    match range.split_once('-') {
+        Some((start, end)) => parse(start)..=parse(end),
+        None => parse(range)..=parse(range),
+    }
+}
+
+// Path for a chart in ./images, creating the directory if needed
+fn image_path(name: &str) -> String {
+    fs::create_dir_all("images").expect("Could not create the images directory");
+    format!("images/{}", name)
+}
+
+// Writes a chart to --output if given, otherwise to `name` in ./images, with
+// --theme dark or light replacing its theme. An output of "-" writes a CGI
+// style response instead: the content type, a blank line and the chart (PNG,
+// or SVG with --format svg) on stdout.
+fn output_chart(mut spec: chart::ChartSpec, name: &str, args: &[String]) {
+    if value(args, "--theme").is_some() {
+        spec.theme = option(args, "--theme", &[("dark", chart::Theme::dark()), ("light", chart::Theme::light())]);
+    }
+    let output = value(args, "--output").map_or_else(|| image_path(name), str::to_string);
+    if output != "-" {
+        chart::to_file(&spec, &output).unwrap();
+        return;
+    }
+    let format = option(args, "--format", &[("png", chart::Format::Png), ("svg", chart::Format::Svg)]);
+    let bytes = chart::to_bytes(&spec, format).unwrap();
+    let mut stdout = std::io::stdout().lock();
+    write!(stdout, "Content-Type: {}\r\n\r\n", format.mime_type()).unwrap();
+    stdout.write_all(&bytes).unwrap();
+}
+
+// Human readable name for the stations we know about
+fn station_name(wban: &str) -> &str {
+    match wban {
+        "13958" => "Camp Mabry",
+        "14735" => "Albany",
+        "11641" => "San Juan",
+        _ => wban,
+    }
+}
+
+// Offset of local standard time from UTC in minutes. Falls back to UTC for
+// stations we don't know about.
+fn station_utc_offset(wban: &str) -> i32 {
+    match wban {
+        "13958" => -6 * 60,
+        "14735" => -5 * 60,
+        "11641" => -4 * 60,
+        _ => 0,
+    }
+}
+
+// Downloads the raw records for a station over several years. The result has
+// one entry per year, in order.
+fn download_raw_years(wban: &str, years: std::ops::RangeInclusive<u32>) -> Result<Vec<Vec<String>>, ureq::Error> {
+    let mut raw_years = Vec::new();
+    for year in years {
+        let mut data = download_data(&year.to_string(), wban, false)?;
+        data.pop(); // Last record is always empty
+        raw_years.push(data);
+    }
+    Ok(raw_years)
+}
+
+// Downloads, validates and parses the data for a station over several years.
+// The result has one entry per year, in order.
+fn download_years(wban: &str, years: std::ops::RangeInclusive<u32>) -> Result<Vec<Vec<TempData>>, ureq::Error> {
+    Ok(valid_years(download_raw_years(wban, years)?))
+}
+
+// Validates and parses raw records, one entry per year
+fn valid_years(raw_years: Vec<Vec<String>>) -> Vec<Vec<TempData>> {
+    let mut station_years = Vec::new();
+    for mut data in raw_years {
+        remove_invalid_entries(&mut data);
+        station_years.push(extract_detailed_temps(&data));
+    }
+    station_years
+}
+
 fn calc_daily_average(location_temps: &Vec<Vec<TempData>>) {
  for location in location_temps {
     // Sort all temps into bins
@@ -68,8 +639,8 @@ fn calc_daily_average(location_temps: &Vec<Vec<TempData>>) {
         let mut second_moment: i64 = 0;
         for temp in day {
             // let x = temp.temp10 as i64 * temp.duration as i64;
-            first_moment += temp.temp10 as i64 * temp.duration as i64;
-            second_moment += temp.temp10 as i64 * temp.temp10 as i64 * temp.duration as i64;
+            first_moment += temp.temp10.tenths() as i64 * temp.duration as i64;
+            second_moment += temp.temp10.tenths() as i64 * temp.temp10.tenths() as i64 * temp.duration as i64;
         }
         let first_moment = first_moment as f64 / 14400.0; // Save all division until the end
         let second_moment = second_moment as f64 / 144000.0;
@@ -77,7 +648,13 @@ fn calc_daily_average(location_temps: &Vec<Vec<TempData>>) {
         let variance = second_moment - (mean * mean);
         let standard_deviation = variance.sqrt();
         day_averages.push(Average { mean, standard_deviation });
-        println!("Intermediate mean: {}, second_moment: {}, sd: {}, variance: {}", mean, second_moment, standard_deviation, variance);
+        // The second moment isn't a temperature, so it is rebuilt from the
+        // mean and standard deviation in the display unit
+        let symbol = units::symbol();
+        let (display_mean, display_sd) = (units::temperature(mean), units::difference(standard_deviation));
+        let squared = units::squared_symbol();
+        println!("Intermediate mean: {:.2} {}, second_moment: {:.2} {}, sd: {:.2} {}, variance: {:.2} {}",
+            display_mean, symbol, display_sd * display_sd + display_mean * display_mean, squared, display_sd, symbol, display_sd * display_sd, squared);
     }
 
     // Calculate number of days used
@@ -94,7 +671,8 @@ fn calc_daily_average(location_temps: &Vec<Vec<TempData>>) {
     let mean = first_moment;
     let variance = second_moment - (mean * mean);
     let standard_deviation = variance.sqrt();
-    println!("Mean: {}, Standard deviation: {}", mean, standard_deviation);
+    let symbol = units::symbol();
+    println!("Mean: {:.2} {}, Standard deviation: {:.2} {}", units::difference(mean), symbol, units::difference(standard_deviation), symbol);
  }   
 }
 
@@ -125,138 +703,6 @@ fn remove_past_day (temperatures: &mut Vec<TempData>, day: u32) {
     temperatures.retain(|temp| temp.minute_of_year < limit);
 }
 
-fn plot_detailed_year_temps(days: &Vec<TempData>) {
-    let root_drawing_area = BitMapBackend::new("images/0.png", (1920, 1080))
-        .into_drawing_area();
-
-    root_drawing_area.fill(&BLACK).unwrap();
-
-    let mut chart: ChartContext<BitMapBackend, Cartesian2d<plotters::coord::types::RangedCoordi32, plotters::coord::types::RangedCoordf64>> = ChartBuilder::on(&root_drawing_area)
-        .set_label_area_size(LabelAreaPosition::Bottom, 140)
-        .set_label_area_size(LabelAreaPosition::Left, 240)
-        .margin(50)
-        // .caption("Temperatures Recorded at Camp Mabry Over a Year", ("sans-serif", 40))
-        .build_cartesian_2d(0..527040, -15.0..50.0)
-        .unwrap();
-
-    chart.configure_mesh()
-        .x_labels(15)
-        .x_label_formatter(&|x| format!("{}", *x / 1440))
-        .x_desc("Progress in Year (Days)")
-        .y_labels(12)
-        .y_desc("Temperature (°F)")
-        .y_label_formatter(&|y| format!("{}", *y * (9.0/5.0) + 32.0))
-        .axis_desc_style(("sans-serif", 70, &WHITE))
-        .axis_style(&WHITE)
-        .label_style(("sans-serif", 70, &WHITE))
-        .draw().unwrap();
-
-    chart.draw_series(
-        days.iter().map(|t| Circle::new((t.minute_of_year as i32, t.temp10 as f64 / 10.0), 2, &WHITE))    
-    )
-    .unwrap()
-    .label("Test");
-}
-
-fn plot_detailed_week_temps(days: &Vec<TempData>) {
-    let root_drawing_area = BitMapBackend::new("images/0.png", (1920, 1080))
-        .into_drawing_area();
-
-    root_drawing_area.fill(&BLACK).unwrap();
-
-    let mut chart: ChartContext<BitMapBackend, Cartesian2d<plotters::coord::types::RangedCoordi32, plotters::coord::types::RangedCoordf64>> = ChartBuilder::on(&root_drawing_area)
-        .set_label_area_size(LabelAreaPosition::Bottom, 140)
-        .set_label_area_size(LabelAreaPosition::Left, 240)
-        .margin(50)
-        // .caption("Temperatures Recorded at Camp Mabry Over a Year", ("sans-serif", 40))
-        .build_cartesian_2d(0..(7*1440), -15.0..50.0)
-        .unwrap();
-
-    chart.configure_mesh()
-        .x_labels(7)
-        .x_label_formatter(&|x| format!("{}", *x / 60))
-        .x_desc("Progress in Week (Hours)")
-        .y_labels(12)
-        .y_desc("Temperature (°F)")
-        .y_label_formatter(&|y| format!("{}", *y * (9.0/5.0) + 32.0))
-        .axis_desc_style(("sans-serif", 70, &WHITE))
-        .axis_style(&WHITE)
-        .label_style(("sans-serif", 70, &WHITE))
-        .draw().unwrap();
-
-    chart.draw_series(
-        days.iter().map(|t| Circle::new((t.minute_of_year as i32, t.temp10 as f64 / 10.0), 3, &WHITE))    
-    )
-    .unwrap()
-    .label("Test");
-}
-
-fn plot_detailed_week_dist(days: &Vec<TempData>) {
-    let root_drawing_area = BitMapBackend::new("images/0.png", (1920, 1080))
-        .into_drawing_area();
-
-    root_drawing_area.fill(&BLACK).unwrap();
-
-    let mut chart: ChartContext<BitMapBackend, Cartesian2d<plotters::coord::types::RangedCoordi32, plotters::coord::types::RangedCoordf64>> = ChartBuilder::on(&root_drawing_area)
-        .set_label_area_size(LabelAreaPosition::Bottom, 140)
-        .set_label_area_size(LabelAreaPosition::Left, 240)
-        .margin(40)
-        // .caption("Temperatures Recorded at Camp Mabry Over a Year", ("sans-serif", 40))
-        .build_cartesian_2d(-150..150, 0f64..1000f64)
-        .unwrap();
-
-    chart.configure_mesh()
-        .x_labels(12)
-        .x_desc("Temperature (°F)")
-        .x_label_formatter(&|x| format!("{:.1}", (*x as f32 / 10.0) * (9.0/5.0) + 32.0))
-        .y_labels(10)
-        .y_label_formatter(&|y| format!("{:.3}", *y as f32 / (7*1440) as f32))
-        .y_desc("Probability")
-        .axis_desc_style(("sans-serif", 70, &WHITE))
-        .axis_style(&WHITE)
-        .label_style(("sans-serif", 50, &WHITE))
-        .draw().unwrap();
-
-        chart.draw_series(
-            Histogram::vertical(&chart)
-                .style(RED.mix(0.8).filled())
-                .data(days.iter().map(|t| (t.temp10 as i32, t.duration as f64))),
-        ).unwrap();
-}
-
-fn plot_detailed_day_temps(days: &Vec<TempData>) {
-    let root_drawing_area = BitMapBackend::new("images/0.png", (1920, 1080))
-        .into_drawing_area();
-
-    root_drawing_area.fill(&BLACK).unwrap();
-
-    let mut chart: ChartContext<BitMapBackend, Cartesian2d<plotters::coord::types::RangedCoordi32, plotters::coord::types::RangedCoordf64>> = ChartBuilder::on(&root_drawing_area)
-        .set_label_area_size(LabelAreaPosition::Bottom, 140)
-        .set_label_area_size(LabelAreaPosition::Left, 240)
-        .margin(50)
-        // .caption("Temperatures Recorded at Camp Mabry Over a Year", ("sans-serif", 40))
-        .build_cartesian_2d(0..(1440), -15.0..50.0)
-        .unwrap();
-
-    chart.configure_mesh()
-        .x_labels(24)
-        .x_label_formatter(&|x| format!("{}", *x / 60))
-        .x_desc("Progress in Week (Hours)")
-        .y_labels(12)
-        .y_desc("Temperature (°F)")
-        .y_label_formatter(&|y| format!("{}", *y * (9.0/5.0) + 32.0))
-        .axis_desc_style(("sans-serif", 70, &WHITE))
-        .axis_style(&WHITE)
-        .label_style(("sans-serif", 70, &WHITE))
-        .draw().unwrap();
-
-    chart.draw_series(
-        days.iter().map(|t| Circle::new((t.minute_of_year as i32, t.temp10 as f64 / 10.0), 3, &WHITE))    
-    )
-    .unwrap()
-    .label("Test");
-}
-
 // Downloads data from the NOAA for a specific WBAN. Requires several discrete
 // steps:
 // - Finding the exact filepath to get the data
@@ -309,16 +755,29 @@ fn download_data(year: &str, wban: &str, save_output: bool) -> Result<Vec<String
 // which have passed all NOAA quality control checks and come from official NOAA
 // sources.
 fn remove_invalid_entries(data: &mut Vec<String>) {    
-    data.retain(
-        |line|
-        (line.get(56..59).unwrap().eq("V03") || line.get(56..59).unwrap().eq("V02")) && line.get(92..93).unwrap().eq("5")
-    );
+    data.retain(|line| rejection_reason(line).is_none());
     // for i in 0..data.len() {
     //     println!("{}", data[i]);
     // }
 
 }
 
+// Why remove_invalid_entries would drop a record, or None if it is kept
+fn rejection_reason(line: &str) -> Option<String> {
+    let process = line.get(56..59).unwrap();
+    if !(process.eq("V03") || process.eq("V02")) {
+        return Some(format!("Quality control process {}", process));
+    }
+    let quality = line.get(92..93).unwrap();
+    if quality.eq("5") {
+        return None;
+    }
+    if line.get(87..92).unwrap().eq("+9999") {
+        return Some("Air temperature missing".to_string());
+    }
+    Some(format!("Air temperature quality code {}", quality))
+}
+
 // Do the bulk of the handling of the data lmao
 fn extract_temps(data: &Vec<String>, save_output: bool) -> Vec<Vec<i16>> {
     let year = usize::from_str_radix(data[0].get(15..19).unwrap(), 10).unwrap(); 
@@ -342,6 +801,9 @@ fn extract_temps(data: &Vec<String>, save_output: bool) -> Vec<Vec<i16>> {
 }
 
 fn extract_detailed_temps(raw_data: &Vec<String>) -> Vec<TempData> {
+    if raw_data.is_empty() {
+        return Vec::new();
+    }
     let year = usize::from_str_radix(raw_data[0].get(15..19).unwrap(), 10).unwrap(); 
     let leap_year = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
     let mut temperatures = Vec::<TempData>::new();
@@ -360,7 +822,7 @@ fn extract_detailed_temps(raw_data: &Vec<String>) -> Vec<TempData> {
         let next_minutes = u32::from_str_radix(next_line.get(25..27).unwrap(), 10).unwrap();
         let next_minute_of_year = (next_day_of_year as u32 - 1) * 1440 + next_hours * 60 + next_minutes;
         // println!("Day: {}, Hours: {}, Minutes: {}, Minutes of year: {}", day_of_year, hours, minutes, minute_of_year);
-        temperatures.push(TempData{temp10: temperature, minute_of_year, duration: (next_minute_of_year - minute_of_year) as u16});        
+        temperatures.push(TempData{temp10: Tenths::new(temperature), minute_of_year, duration: (next_minute_of_year - minute_of_year) as u16});        
     }
     let line = &raw_data[raw_data.len()-1];
     let day_of_year = get_day_of_year(line.get(19..23).unwrap(), leap_year);
@@ -368,17 +830,26 @@ fn extract_detailed_temps(raw_data: &Vec<String>) -> Vec<TempData> {
     let hours = u32::from_str_radix(line.get(23..25).unwrap(), 10).unwrap();
     let minutes = u32::from_str_radix(line.get(25..27).unwrap(), 10).unwrap();
     let minute_of_year = (day_of_year as u32 - 1) * 1440 + hours * 60 + minutes;
-    temperatures.push(TempData{temp10: temperature, minute_of_year, duration: 1440u16 - (temperatures[temperatures.len() - 1].minute_of_year as u16 % 1440u16)});
+    // The last record lasts until the end of its own day
+    temperatures.push(TempData{temp10: Tenths::new(temperature), minute_of_year, duration: (1440 - minute_of_year % 1440) as u16});
     // We now have an array of 
     return temperatures;
 }
 
 struct TempData {
-    temp10: i16,
+    temp10: Tenths<Celsius>,
     duration: u16, // duration in minutes until next sample. Will be used to calculate probability.
     minute_of_year: u32
 }
 
+// Test fixture: one reading per day for `days` days, held all day
+#[cfg(test)]
+fn year_of(days: usize, temp: impl Fn(usize) -> f64) -> Vec<TempData> {
+    (0..days)
+        .map(|day| TempData { temp10: Tenths::from_degrees(temp(day)), duration: 1440, minute_of_year: day as u32 * 1440 })
+        .collect()
+}
+
 // Gets the body from an HTTP request to a website
 // async fn get_http_body(url: &str) -> Result<String, reqwest::Error> {
 //     return reqwest::get(url)
@@ -470,8 +941,10 @@ fn process_temps(locations: &Vec<Vec<Vec<i16>>>) {
 
     for location in city_averages {
         let (mean, sd) = location;
-        println!("City mean: {} +- {}\nCity SD: {} +- {}", 
-            mean.mean, mean.standard_deviation, sd.mean, sd.standard_deviation);
+        let symbol = units::symbol();
+        println!("City mean: {:.2} {} +- {:.2} {}\nCity SD: {:.2} {} +- {:.2} {}", 
+            units::temperature(mean.mean), symbol, units::difference(mean.standard_deviation), symbol,
+            units::difference(sd.mean), symbol, units::difference(sd.standard_deviation), symbol);
     }
 }   
 
@@ -494,4 +967,30 @@ struct Average {
     
     return day + months[month - 1];
   }
-   */
\ No newline at end of file
+   */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A record which passes remove_invalid_entries, with only the fields
+    // extract_detailed_temps reads filled in
+    fn record(date_time: &str, temp: &str) -> String {
+        let mut line = "0".repeat(105);
+        line.replace_range(15..27, date_time);
+        line.replace_range(56..59, "V02");
+        line.replace_range(87..93, temp);
+        line
+    }
+
+    #[test]
+    fn detailed_temps_of_sparse_years() {
+        assert!(extract_detailed_temps(&Vec::new()).is_empty());
+        let single = extract_detailed_temps(&vec![record("202203011800", "+01505")]);
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].minute_of_year, 59 * 1440 + 18 * 60);
+        assert_eq!(single[0].duration, 6 * 60);
+        let pair = extract_detailed_temps(&vec![record("202203011800", "+01505"), record("202203021200", "+02005")]);
+        assert_eq!(pair[0].duration, 18 * 60);
+        assert_eq!(pair[1].duration, 12 * 60);
+    }
+}
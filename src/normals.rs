@@ -0,0 +1,90 @@
+use crate::daily::{self, DailyStats, DailyVariable};
+use crate::stats;
+use crate::TempData;
+
+/*
+Daily normals: for every day of the year, the mean and standard deviation of
+a daily variable over many years, along with the record high and low. The
+mean and spread pool the days within `window` days either side so that a
+single odd year doesn't leave a spike in the normal; records are kept for the
+exact calendar day. Days are indexed by daily::calendar_day(), so 1 March is
+the same day in leap and other years.
+*/
+
+#[derive(Clone, Copy, Debug)]
+pub struct DailyNormal {
+    pub mean: f64, // °C
+    pub standard_deviation: f64,
+    pub record_high: f64, // Highest daily max on this day
+    pub record_low: f64, // Lowest daily min on this day
+}
+
+impl DailyNormal {
+    // How many standard deviations `value` is from the mean
+    pub fn z_score(&self, value: f64) -> f64 {
+        (value - self.mean) / self.standard_deviation
+    }
+}
+
+// One entry per calendar day (366), None where no year has data
+pub fn daily_normals(days_by_year: &[Vec<Option<DailyStats>>], variable: DailyVariable, window: usize) -> Vec<Option<DailyNormal>> {
+    (0..366)
+        .map(|day| {
+            let mut values = Vec::new();
+            for offset in 0..=(2 * window) {
+                let other = (day + 366 + offset - window) % 366;
+                for days in days_by_year {
+                    if let Some(stats) = days[other] {
+                        values.push(stats.get(variable));
+                    }
+                }
+            }
+            let on_day: Vec<&DailyStats> = days_by_year.iter().filter_map(|days| days[day].as_ref()).collect();
+            if values.len() < 2 || on_day.is_empty() {
+                return None;
+            }
+            Some(DailyNormal {
+                mean: stats::mean(&values),
+                standard_deviation: stats::variance(&values).sqrt(),
+                record_high: on_day.iter().map(|s| s.max).fold(f64::NEG_INFINITY, f64::max),
+                record_low: on_day.iter().map(|s| s.min).fold(f64::INFINITY, f64::min),
+            })
+        })
+        .collect()
+}
+
+// Normals of every year in `years`, which start at first_year
+pub fn normals(years: &[Vec<TempData>], first_year: u32, variable: DailyVariable, window: usize) -> Vec<Option<DailyNormal>> {
+    let days_by_year: Vec<Vec<Option<DailyStats>>> =
+        years.iter().zip(first_year..).map(|(temps, year)| daily::calendar_daily_stats(temps, year)).collect();
+    daily_normals(&days_by_year, variable, window)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Tenths;
+
+    // A year at 0 °C except for 1 March at `degrees`
+    fn year_with_warm_march_first(year: u32, degrees: f64) -> Vec<TempData> {
+        let march_first = if daily::is_leap_year(year) { 60 } else { 59 };
+        let days = if daily::is_leap_year(year) { 366 } else { 365 };
+        (0..days)
+            .map(|day| {
+                let temp = if day == march_first { degrees } else { 0.0 };
+                TempData { temp10: Tenths::from_degrees(temp), duration: 1440, minute_of_year: day * 1440 }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn leap_and_other_years_line_up_after_february() {
+        let years = [year_with_warm_march_first(2003, 10.0), year_with_warm_march_first(2004, 12.0)];
+        let normals = normals(&years, 2003, DailyVariable::Mean, 0);
+        let march_first = normals[60].unwrap();
+        assert_eq!((march_first.mean, march_first.record_low, march_first.record_high), (11.0, 10.0, 12.0));
+        // Only the leap year has a 29 February, which isn't enough for a normal
+        assert!(normals[59].is_none());
+        assert_eq!(normals[61].unwrap().mean, 0.0);
+    }
+}
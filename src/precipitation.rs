@@ -0,0 +1,257 @@
+use crate::daily;
+use crate::isd::Precipitation;
+use crate::stats;
+
+/*
+Precipitation from the AA1-AA4 groups. A station reports totals over several
+periods at once (hourly in the METARs, 6 hourly in the synoptic reports, 24
+hours in the daily summary), which overlap, so they can't simply be added up.
+Instead the reports are laid out minute by minute, shortest period first:
+each report only adds what its total has beyond the shorter reports inside
+it, spread evenly over the minutes they don't cover. A day counts as having
+data once `min_coverage` of its minutes are covered by some report.
+
+Wet days have at least 1 mm (the WMO definition), dry days less. Depths are
+in mm. Days without data end a spell.
+*/
+
+pub const WET_DAY: f64 = 1.0;
+
+// Share of a month's days needed for the monthly (and so annual) totals in
+// precipitation_stats
+pub const MONTH_COVERAGE: f64 = 0.9;
+
+// Percentiles of the wet day amounts reported by precipitation_stats
+pub const INTENSITY_PERCENTILES: [f64; 4] = [50.0, 90.0, 95.0, 99.0];
+
+// Depth (mm) in every minute of the year, and whether each minute is covered
+// by any report
+fn minute_depths(reports: &[Precipitation], year: u32) -> (Vec<f64>, Vec<bool>) {
+    let minutes = if daily::is_leap_year(year) { 366 * 1440 } else { 365 * 1440 };
+    let mut depths = vec![0.0; minutes];
+    let mut covered = vec![false; minutes];
+    let mut sorted = reports.to_vec();
+    sorted.sort_by_key(|r| (r.period, r.minute_of_year));
+    // The same period often comes in more than one report type
+    sorted.dedup_by_key(|r| (r.period, r.minute_of_year));
+    for report in sorted {
+        let end = report.minute_of_year as i64;
+        let start = end - report.period as i64;
+        let range = start.max(0) as usize..(end.max(0) as usize).min(minutes);
+        // Minutes before the start of the year count as uncovered
+        let outside = (-start).clamp(0, report.period as i64) as usize;
+        let already: f64 = range.clone().filter(|&m| covered[m]).map(|m| depths[m]).sum();
+        let uncovered = outside + range.clone().filter(|&m| !covered[m]).count();
+        if uncovered == 0 {
+            continue;
+        }
+        let per_minute = (report.depth - already).max(0.0) / uncovered as f64;
+        for minute in range {
+            if !covered[minute] {
+                depths[minute] = per_minute;
+                covered[minute] = true;
+            }
+        }
+    }
+    (depths, covered)
+}
+
+// Total (mm) for every day of `year` (366 entries), None for days where less
+// than `min_coverage` (0..1) of the day is covered
+pub fn daily_totals(reports: &[Precipitation], year: u32, min_coverage: f64) -> Vec<Option<f64>> {
+    let (depths, covered) = minute_depths(reports, year);
+    let mut days: Vec<Option<f64>> = depths
+        .chunks(1440)
+        .zip(covered.chunks(1440))
+        .map(|(depths, covered)| {
+            let coverage = covered.iter().filter(|c| **c).count() as f64 / 1440.0;
+            (coverage >= min_coverage).then(|| depths.iter().sum())
+        })
+        .collect();
+    days.resize(366, None);
+    days
+}
+
+// Monthly totals (mm) from daily totals. A month needs `min_coverage` (0..1)
+// of its days; days without data are filled with the mean of the month's
+// other days.
+pub fn monthly_totals(year: u32, days: &[Option<f64>], min_coverage: f64) -> [Option<f64>; 12] {
+    let days_in_year = if daily::is_leap_year(year) { 366 } else { 365 };
+    let mut sums = [0.0; 12];
+    let mut present = [0usize; 12];
+    let mut lengths = [0usize; 12];
+    for (day_index, day) in days.iter().enumerate().take(days_in_year) {
+        let (month, _) = daily::month_and_day(year, day_index);
+        lengths[month - 1] += 1;
+        if let Some(depth) = day {
+            sums[month - 1] += depth;
+            present[month - 1] += 1;
+        }
+    }
+    std::array::from_fn(|month| {
+        let enough = present[month] > 0 && present[month] as f64 >= min_coverage * lengths[month] as f64;
+        enough.then(|| sums[month] / present[month] as f64 * lengths[month] as f64)
+    })
+}
+
+// Annual total (mm), only when every month has one
+pub fn annual_total(months: &[Option<f64>; 12]) -> Option<f64> {
+    months.iter().copied().sum()
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Spell {
+    pub days: usize,
+    pub year: u32, // Of the first day
+    pub start: usize, // Day index of the first day
+}
+
+#[derive(Clone, Debug)]
+pub struct PrecipitationStats {
+    pub days: usize, // Days with data
+    pub wet_days: usize,
+    pub wet_day_frequency: f64, // % of the days with data
+    pub mean_intensity: f64, // mm per wet day (SDII)
+    pub intensity_percentiles: Vec<(f64, f64)>, // (percentile, mm) of the wet day amounts
+    pub mean_annual_total: Option<f64>, // mm, over the complete years
+    pub annual_variability: Option<f64>, // Coefficient of variation of the annual totals, %
+    pub longest_dry: Option<Spell>,
+    pub longest_wet: Option<Spell>,
+}
+
+// Longest run of days with data for which `condition` holds. Runs carry on
+// from one year into the next.
+fn longest_spell(years: &[Vec<Option<f64>>], first_year: u32, condition: impl Fn(f64) -> bool) -> Option<Spell> {
+    let mut longest: Option<Spell> = None;
+    let mut current: Option<Spell> = None;
+    for (days, year) in years.iter().zip(first_year..) {
+        let days_in_year = if daily::is_leap_year(year) { 366 } else { 365 };
+        for (day_index, day) in days.iter().enumerate().take(days_in_year) {
+            match day {
+                Some(depth) if condition(*depth) => {
+                    let spell = current.get_or_insert(Spell { days: 0, year, start: day_index });
+                    spell.days += 1;
+                    if longest.is_none_or(|l| spell.days > l.days) {
+                        longest = Some(*spell);
+                    }
+                }
+                _ => current = None,
+            }
+        }
+    }
+    longest
+}
+
+// Statistics over daily totals for several years, `years[0]` being
+// `first_year`
+pub fn precipitation_stats(years: &[Vec<Option<f64>>], first_year: u32) -> PrecipitationStats {
+    let observed: Vec<f64> = years
+        .iter()
+        .zip(first_year..)
+        .flat_map(|(days, year)| days.iter().take(if daily::is_leap_year(year) { 366 } else { 365 }))
+        .flatten()
+        .copied()
+        .collect();
+    let wet: Vec<f64> = observed.iter().copied().filter(|d| *d >= WET_DAY).collect();
+    let annual: Vec<f64> = years
+        .iter()
+        .zip(first_year..)
+        .filter_map(|(days, year)| annual_total(&monthly_totals(year, days, MONTH_COVERAGE)))
+        .collect();
+    let mean_annual_total = (!annual.is_empty()).then(|| stats::mean(&annual));
+    PrecipitationStats {
+        days: observed.len(),
+        wet_days: wet.len(),
+        wet_day_frequency: wet.len() as f64 / observed.len().max(1) as f64 * 100.0,
+        mean_intensity: stats::mean(&wet),
+        intensity_percentiles: INTENSITY_PERCENTILES.iter().map(|&p| (p, stats::quantile(&wet, p / 100.0))).collect(),
+        mean_annual_total,
+        annual_variability: (annual.len() > 1).then(|| stats::variance(&annual).sqrt() / stats::mean(&annual) * 100.0),
+        longest_dry: longest_spell(years, first_year, |d| d < WET_DAY),
+        longest_wet: longest_spell(years, first_year, |d| d >= WET_DAY),
+    }
+}
+
+pub fn print_monthly_totals(station: &str, first_year: u32, years: &[[Option<f64>; 12]]) {
+    println!("{} precipitation (mm)", station);
+    print!("{:<6}", "Year");
+    for month in daily::MONTH_NAMES {
+        print!("{:>7}", month);
+    }
+    println!("{:>8}", "Total");
+    let format = |depth: Option<f64>, width: usize| depth.map_or(format!("{:>width$}", "-"), |d| format!("{:>width$.1}", d));
+    for (months, year) in years.iter().zip(first_year..) {
+        print!("{:<6}", year);
+        for month in months {
+            print!("{}", format(*month, 7));
+        }
+        println!("{}", format(annual_total(months), 8));
+    }
+}
+
+pub fn print_precipitation_stats(station: &str, stats: &PrecipitationStats) {
+    println!("{} precipitation over {} days with data:", station, stats.days);
+    if let Some(total) = stats.mean_annual_total {
+        let variability = stats.annual_variability.map_or(String::new(), |cv| format!(", varying {:.0}% from year to year", cv));
+        println!("    Mean annual total {:.0} mm{}", total, variability);
+    }
+    println!(
+        "    {} wet days (>= {:.0} mm), {:.1}% of days, {:.1} mm per wet day",
+        stats.wet_days, WET_DAY, stats.wet_day_frequency, stats.mean_intensity
+    );
+    let percentiles: Vec<String> = stats.intensity_percentiles.iter().map(|(p, d)| format!("{:.0}th {:.1} mm", p, d)).collect();
+    println!("    Wet day amounts: {}", percentiles.join(", "));
+    for (name, spell) in [("dry", stats.longest_dry), ("wet", stats.longest_wet)] {
+        if let Some(spell) = spell {
+            println!("    Longest {} spell {} days from {}", name, spell.days, daily::format_date(spell.year, spell.start));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(hour: u32, hours: u32, depth: f64) -> Precipitation {
+        Precipitation { minute_of_year: hour * 60, period: hours * 60, depth }
+    }
+
+    #[test]
+    fn overlapping_reports_are_counted_once() {
+        let mut reports: Vec<Precipitation> = (1..=6).map(|hour| report(hour, 1, 1.0)).collect();
+        reports.extend([
+            report(6, 1, 1.0), // The same hour from another report type
+            report(6, 6, 8.0), // Already covered by the hourly reports, so adds nothing
+            report(12, 6, 3.0), // No hourly reports inside it
+            report(24, 24, 12.0), // 9 mm so far, the other 3 mm go in the last 12 hours
+        ]);
+        let (depths, covered) = minute_depths(&reports, 2022);
+        assert!(covered[..1440].iter().all(|c| *c) && !covered[1440]);
+        assert!((depths[..360].iter().sum::<f64>() - 6.0).abs() < 1e-9);
+        assert!((depths[360..720].iter().sum::<f64>() - 3.0).abs() < 1e-9);
+        assert!((depths[720..1440].iter().sum::<f64>() - 3.0).abs() < 1e-9);
+        let days = daily_totals(&reports, 2022, 0.9);
+        assert!((days[0].unwrap() - 12.0).abs() < 1e-9);
+        assert_eq!(days[1], None);
+        // A trace covers its hour without adding to it
+        let (depths, covered) = minute_depths(&[report(1, 1, 0.0)], 2022);
+        assert!(covered[..60].iter().all(|c| *c) && depths[..60].iter().all(|d| *d == 0.0));
+    }
+
+    #[test]
+    fn spells_carry_over_the_new_year_and_end_at_gaps() {
+        let mut last_year = vec![Some(0.0); 366];
+        last_year[362..365].fill(Some(5.0)); // Dec 29-31, 2021
+        let mut this_year = vec![Some(0.0); 366];
+        this_year[0..2].fill(Some(2.0));
+        this_year[10..14].fill(Some(1.0));
+        this_year[12] = None;
+        let stats = precipitation_stats(&[last_year, this_year], 2021);
+        let wet = stats.longest_wet.unwrap();
+        assert_eq!((wet.days, wet.year, wet.start), (5, 2021, 362));
+        // The dry spell from Jan 3rd to Dec 28th 2021 is the longest
+        let dry = stats.longest_dry.unwrap();
+        assert_eq!((dry.days, dry.year, dry.start), (362, 2021, 0));
+        assert_eq!(stats.wet_days, 8);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::TempData;
+
+/*
+Resampling of the irregular observations onto a regular grid (eg. hourly).
+METARs arrive at :51, specials at any minute, and there are gaps, but most
+analyses want one value every N minutes. Each grid point is marked with where
+its value came from so that gaps are never hidden:
+    - Observed: an observation was taken within half an interval of the point
+    - Interpolated: filled from the observations either side of it
+    - Missing: the observations either side are more than `max_gap` apart
+Missing points can later be filled in by impute.rs, which marks them Imputed.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Method {
+    // Each observation holds until the next one, like TempData's durations
+    StepHold,
+    Linear,
+    Nearest,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sample {
+    Observed(f64),
+    Interpolated(f64),
+    Imputed(f64),
+    Missing,
+}
+
+impl Sample {
+    pub fn value(&self) -> Option<f64> {
+        match *self {
+            Sample::Observed(v) | Sample::Interpolated(v) | Sample::Imputed(v) => Some(v),
+            Sample::Missing => None,
+        }
+    }
+}
+
+pub struct ResampledSeries {
+    pub interval: u32, // minutes
+    pub samples: Vec<Sample>, // Sample i is at minute i * interval of the year
+}
+
+impl ResampledSeries {
+    pub fn minute_of_year(&self, index: usize) -> u32 {
+        index as u32 * self.interval
+    }
+}
+
+// Resamples a year of observations (sorted by time) onto a grid every
+// `interval` minutes covering the whole year. `max_gap` is the longest span
+// between two observations, in minutes, that will be filled. Returns None
+// for an interval of zero.
+pub fn resample(temps: &[TempData], year: u32, interval: u32, method: Method, max_gap: u32) -> Option<ResampledSeries> {
+    if interval == 0 {
+        return None;
+    }
+    let days: u32 = if crate::daily::is_leap_year(year) { 366 } else { 365 };
+    let count = (days * 1440).div_ceil(interval);
+    let mut samples = Vec::with_capacity(count as usize);
+    let mut next = 0; // Index of the first observation after the grid point
+    for i in 0..count {
+        let minute = i * interval;
+        while next < temps.len() && temps[next].minute_of_year <= minute {
+            next += 1;
+        }
+        let Some(previous) = next.checked_sub(1).map(|p| &temps[p]) else {
+            samples.push(Sample::Missing); // Before the first observation
+            continue;
+        };
+        // The last observation of the year has nothing after it, so it lasts
+        // for its duration
+        let (next_minute, next_value) = match temps.get(next) {
+            Some(t) => (t.minute_of_year, t.temp10.degrees()),
+            None => (previous.minute_of_year + previous.duration as u32, previous.temp10.degrees()),
+        };
+        let previous_value = previous.temp10.degrees();
+        if minute >= next_minute || next_minute - previous.minute_of_year > max_gap {
+            samples.push(if minute == previous.minute_of_year { Sample::Observed(previous_value) } else { Sample::Missing });
+            continue;
+        }
+
+        let since = minute - previous.minute_of_year;
+        let until = next_minute - minute;
+        let value = match method {
+            Method::StepHold => previous_value,
+            Method::Linear => {
+                let fraction = since as f64 / (next_minute - previous.minute_of_year) as f64;
+                previous_value + (next_value - previous_value) * fraction
+            }
+            Method::Nearest => {
+                if since <= until {
+                    previous_value
+                } else {
+                    next_value
+                }
+            }
+        };
+        let half = interval / 2;
+        let observed_nearby = since <= half || (until <= half && next < temps.len());
+        samples.push(if observed_nearby { Sample::Observed(value) } else { Sample::Interpolated(value) });
+    }
+    Some(ResampledSeries { interval, samples })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Tenths;
+
+    fn temp(minute_of_year: u32, degrees: f64, duration: u16) -> TempData {
+        TempData { temp10: Tenths::from_degrees(degrees), duration, minute_of_year }
+    }
+
+    #[test]
+    fn resamples_onto_a_grid() {
+        assert!(resample(&[], 2022, 0, Method::Linear, 180).is_none());
+        let temps = [temp(0, 10.0, 120), temp(120, 20.0, 60), temp(600, 30.0, 60)];
+        let series = resample(&temps, 2022, 60, Method::Linear, 180).unwrap();
+        assert_eq!(series.samples.len(), 365 * 24);
+        assert_eq!(series.samples[0], Sample::Observed(10.0));
+        assert_eq!(series.samples[1], Sample::Interpolated(15.0));
+        assert_eq!(series.samples[2], Sample::Observed(20.0));
+        // 8 hours between observations is more than max_gap
+        assert_eq!(series.samples[5], Sample::Missing);
+        assert_eq!(series.samples[10], Sample::Observed(30.0));
+        assert_eq!(series.samples[12], Sample::Missing);
+    }
+}
@@ -0,0 +1,270 @@
+use crate::diurnal;
+use crate::stats;
+use crate::stl;
+use crate::strangeness::Metric;
+use crate::units;
+use crate::TempData;
+
+/*
+Spectral and autocorrelation analysis. This doesn't depend on any calendar
+binning: the periodogram shows how much of a station's variance is in the
+daily and yearly cycles, and whatever is left over is the irregular part of
+its weather. The autocorrelation of the daily anomalies gives how long a warm
+or cold spell tends to persist.
+
+Missing values are filled with the series mean before the FFT, which spreads a
+little power across every frequency but keeps the spacing regular.
+*/
+
+pub struct SpectralReport {
+    pub diurnal_strength: f64, // Fraction of the hourly variance in the daily cycle and its harmonics
+    pub annual_strength: f64, // Fraction of the daily variance in the yearly cycle and its harmonics
+    pub residual_fraction: f64, // Fraction of the hourly variance in neither cycle
+    pub residual_variance: f64, // °C², same as above in absolute terms
+    pub persistence: f64, // Days until the autocorrelation of the anomalies drops below 1/e
+    pub acf: Vec<f64>, // Autocorrelation of the daily anomalies, lag 0 first
+    pub pacf: Vec<f64>, // Partial autocorrelation, lag 1 first
+    pub missing_fraction: f64, // Fraction of hours without data
+}
+
+// In-place iterative radix-2 FFT. `re` and `im` must have a power of two length.
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut length = 2;
+    while length <= n {
+        let angle = -2.0 * std::f64::consts::PI / length as f64;
+        for start in (0..n).step_by(length) {
+            for k in 0..length / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + length / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        length <<= 1;
+    }
+}
+
+// Periodogram of a regularly spaced series. Returns (frequency in cycles per
+// day, power) for every frequency from zero up to Nyquist. The powers sum to
+// the variance of the series. `samples_per_day` is 24 for hourly data.
+pub fn periodogram(values: &[Option<f64>], samples_per_day: f64) -> Vec<(f64, f64)> {
+    let present: Vec<f64> = values.iter().flatten().copied().collect();
+    let mean = stats::mean(&present);
+    let n = values.len();
+    let padded = n.next_power_of_two();
+    let mut re: Vec<f64> = values.iter().map(|v| v.map_or(0.0, |v| v - mean)).collect();
+    re.resize(padded, 0.0);
+    let mut im = vec![0.0; padded];
+    fft(&mut re, &mut im);
+    // Scaled so that the one sided spectrum sums to the variance
+    let scale = 1.0 / (n as f64 * padded as f64);
+    (1..=padded / 2)
+        .map(|k| {
+            let power = (re[k] * re[k] + im[k] * im[k]) * scale * if k == padded / 2 { 1.0 } else { 2.0 };
+            (k as f64 * samples_per_day / padded as f64, power)
+        })
+        .collect()
+}
+
+// Fraction of the total power within `half_width` cycles per day of the
+// first `harmonics` multiples of `frequency`
+fn band_fraction(spectrum: &[(f64, f64)], frequency: f64, harmonics: usize, half_width: f64) -> f64 {
+    let total: f64 = spectrum.iter().map(|s| s.1).sum();
+    let in_band: f64 = spectrum
+        .iter()
+        .filter(|(f, _)| (1..=harmonics).any(|h| (f - h as f64 * frequency).abs() <= half_width))
+        .map(|s| s.1)
+        .sum();
+    in_band / total
+}
+
+// Autocorrelation for lags 0..=max_lag, skipping pairs where either value is
+// missing
+pub fn acf(values: &[Option<f64>], max_lag: usize) -> Vec<f64> {
+    let present: Vec<f64> = values.iter().flatten().copied().collect();
+    let mean = stats::mean(&present);
+    let variance = present.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / present.len() as f64;
+    (0..=max_lag)
+        .map(|lag| {
+            let pairs: Vec<f64> = values
+                .iter()
+                .zip(values.iter().skip(lag))
+                .filter_map(|(a, b)| Some((a.as_ref()? - mean) * (b.as_ref()? - mean)))
+                .collect();
+            stats::mean(&pairs) / variance
+        })
+        .collect()
+}
+
+// Partial autocorrelation from the autocorrelation via Durbin-Levinson
+pub fn pacf(acf: &[f64]) -> Vec<f64> {
+    let mut pacf = Vec::new();
+    let mut phi: Vec<f64> = Vec::new();
+    for k in 1..acf.len() {
+        let numerator = acf[k] - (1..k).map(|j| phi[j - 1] * acf[k - j]).sum::<f64>();
+        let denominator = 1.0 - (1..k).map(|j| phi[j - 1] * acf[j]).sum::<f64>();
+        let phi_kk = numerator / denominator;
+        let mut next: Vec<f64> = (1..k).map(|j| phi[j - 1] - phi_kk * phi[k - j - 1]).collect();
+        next.push(phi_kk);
+        phi = next;
+        pacf.push(phi_kk);
+    }
+    pacf
+}
+
+// Daily means minus the mean of that calendar day over every year
+fn daily_anomalies(years: &[Vec<TempData>], first_year: u32) -> Vec<Option<f64>> {
+    let (values, day_indices) = stl::daily_mean_series(years, first_year);
+    let mut sums = [0.0; 366];
+    let mut counts = [0usize; 366];
+    for (value, &day) in values.iter().zip(&day_indices) {
+        if let Some(v) = value {
+            sums[day] += v;
+            counts[day] += 1;
+        }
+    }
+    values
+        .iter()
+        .zip(&day_indices)
+        .map(|(value, &day)| value.map(|v| v - sums[day] / counts[day] as f64))
+        .collect()
+}
+
+const DAYS_PER_YEAR: f64 = 365.25;
+
+pub fn spectral_report(years: &[Vec<TempData>], first_year: u32, utc_offset: i32, max_lag: usize) -> SpectralReport {
+    let hourly: Vec<Option<f64>> = years
+        .iter()
+        .zip(first_year..)
+        .flat_map(|(temps, year)| {
+            let days = if crate::daily::is_leap_year(year) { 366 } else { 365 };
+            diurnal::hourly_profiles(temps, utc_offset).into_iter().take(days).flatten()
+        })
+        .collect();
+    let hourly_spectrum = periodogram(&hourly, 24.0);
+    let diurnal_strength = band_fraction(&hourly_spectrum, 1.0, 4, 2.0 / DAYS_PER_YEAR);
+    let annual_in_hourly = band_fraction(&hourly_spectrum, 1.0 / DAYS_PER_YEAR, 3, 0.25 / DAYS_PER_YEAR);
+    let residual_fraction = 1.0 - diurnal_strength - annual_in_hourly;
+    let hourly_variance = hourly_spectrum.iter().map(|s| s.1).sum::<f64>();
+
+    let (daily, _) = stl::daily_mean_series(years, first_year);
+    let annual_strength = band_fraction(&periodogram(&daily, 1.0), 1.0 / DAYS_PER_YEAR, 3, 0.25 / DAYS_PER_YEAR);
+
+    let acf = acf(&daily_anomalies(years, first_year), max_lag);
+    let persistence = acf
+        .windows(2)
+        .enumerate()
+        .find(|(_, w)| w[1] < (-1f64).exp())
+        .map(|(lag, w)| {
+            // Interpolate between the two lags either side of 1/e
+            lag as f64 + (w[0] - (-1f64).exp()) / (w[0] - w[1])
+        })
+        .unwrap_or(max_lag as f64);
+    let pacf = pacf(&acf);
+
+    SpectralReport {
+        diurnal_strength,
+        annual_strength,
+        residual_fraction,
+        residual_variance: residual_fraction * hourly_variance,
+        persistence,
+        acf,
+        pacf,
+        missing_fraction: hourly.iter().filter(|v| v.is_none()).count() as f64 / hourly.len() as f64,
+    }
+}
+
+pub fn spectral_metrics(report: &SpectralReport) -> Vec<Metric> {
+    vec![
+        Metric { name: "Diurnal cycle strength".to_string(), value: report.diurnal_strength * 100.0, unit: "%" },
+        Metric { name: "Annual cycle strength".to_string(), value: report.annual_strength * 100.0, unit: "%" },
+        Metric { name: "Irregular spectral power".to_string(), value: report.residual_fraction * 100.0, unit: "%" },
+        Metric { name: "Irregular variance".to_string(), value: units::difference(units::difference(report.residual_variance)), unit: units::squared_symbol() },
+        Metric { name: "Anomaly persistence".to_string(), value: report.persistence, unit: "d" },
+    ]
+}
+
+pub fn print_spectral_report(station: &str, report: &SpectralReport) {
+    println!("Spectral analysis of {} ({:.1}% of hours missing):", station, report.missing_fraction * 100.0);
+    println!("  Daily cycle: {:.1}% of hourly variance", report.diurnal_strength * 100.0);
+    println!("  Yearly cycle: {:.1}% of daily variance", report.annual_strength * 100.0);
+    println!(
+        "  Irregular: {:.1}% of hourly variance ({:.2} {})",
+        report.residual_fraction * 100.0,
+        units::difference(units::difference(report.residual_variance)),
+        units::squared_symbol()
+    );
+    println!("  Anomalies persist for {:.1} days", report.persistence);
+    println!("  Lag (days)    ACF   PACF");
+    for (lag, r) in report.acf.iter().enumerate().skip(1).take(10) {
+        println!("  {:>10} {:>6.2} {:>6.2}", lag, r, report.pacf[lag - 1]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periodogram_sums_to_the_variance() {
+        // Parseval's theorem, with and without zero padding
+        let mut random = stats::Random::new(5);
+        for n in [1024, 1000] {
+            let values: Vec<Option<f64>> = (0..n).map(|_| Some(3.0 + 2.0 * random.normal())).collect();
+            let present: Vec<f64> = values.iter().flatten().copied().collect();
+            let mean = stats::mean(&present);
+            let variance = present.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / n as f64;
+            let total: f64 = periodogram(&values, 24.0).iter().map(|s| s.1).sum();
+            assert!((total - variance).abs() < 1e-9 * variance, "n = {}: {} against {}", n, total, variance);
+        }
+    }
+
+    #[test]
+    fn a_daily_cycle_lands_on_one_cycle_per_day() {
+        // 64 days of 45 minute readings (a power of two, so no padding) with a
+        // 5 °C amplitude, so variance 12.5
+        let values: Vec<Option<f64>> =
+            (0..64 * 32).map(|sample| Some(5.0 * (2.0 * std::f64::consts::PI * sample as f64 / 32.0).sin())).collect();
+        let spectrum = periodogram(&values, 32.0);
+        let peak = spectrum.iter().copied().max_by(|a, b| a.1.total_cmp(&b.1)).unwrap();
+        assert!((peak.0 - 1.0).abs() < 1e-9);
+        assert!((peak.1 - 12.5).abs() < 1e-6);
+        assert!((band_fraction(&spectrum, 1.0, 1, 0.01) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn pacf_of_an_ar1_series_cuts_off_after_lag_one() {
+        let mut random = stats::Random::new(9);
+        let mut previous = 0.0;
+        let values: Vec<Option<f64>> = (0..20_000)
+            .map(|_| {
+                previous = 0.7 * previous + random.normal();
+                Some(previous)
+            })
+            .collect();
+        let acf = acf(&values, 5);
+        assert_eq!(acf[0], 1.0);
+        assert!((acf[2] - 0.49).abs() < 0.03, "{:?}", acf);
+        let pacf = pacf(&acf);
+        assert!((pacf[0] - 0.7).abs() < 0.03, "{:?}", pacf);
+        assert!(pacf[1..].iter().all(|p| p.abs() < 0.03), "{:?}", pacf);
+    }
+}
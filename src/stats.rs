@@ -0,0 +1,456 @@
+// Small numerical helpers shared by the analysis modules. Nothing in here knows
+// about weather data; it all works on plain f64 slices.
+
+// Arithmetic mean. Returns NaN for an empty slice so that missing data shows up
+// in printed output rather than silently becoming zero.
+pub fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+// Sample variance (n - 1 denominator)
+pub fn variance(values: &[f64]) -> f64 {
+    if values.len() < 2 {
+        return f64::NAN;
+    }
+    let m = mean(values);
+    values.iter().map(|v| (v - m) * (v - m)).sum::<f64>() / (values.len() - 1) as f64
+}
+
+// Quantile of already sorted data using linear interpolation between closest
+// ranks. `p` is in 0..=1.
+pub fn quantile_sorted(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return f64::NAN;
+    }
+    let position = p.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let lower = position.floor() as usize;
+    let upper = position.ceil() as usize;
+    let fraction = position - lower as f64;
+    sorted[lower] + (sorted[upper] - sorted[lower]) * fraction
+}
+
+// Sorts a copy of the data and returns the requested quantile
+pub fn quantile(values: &[f64], p: f64) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    quantile_sorted(&sorted, p)
+}
+
+// Quantile of (value, weight) pairs: the first value at which the cumulative
+// weight reaches `p` of the total. Used with durations as weights so that a
+// reading which held for an hour counts for more than one that held for five
+// minutes.
+pub fn weighted_quantile(values: &[(f64, f64)], p: f64) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let total: f64 = sorted.iter().map(|v| v.1).sum();
+    if sorted.is_empty() || total <= 0.0 {
+        return f64::NAN;
+    }
+    let target = p.clamp(0.0, 1.0) * total;
+    let mut cumulative = 0.0;
+    for &(value, weight) in &sorted {
+        cumulative += weight;
+        if cumulative >= target {
+            return value;
+        }
+    }
+    sorted[sorted.len() - 1].0
+}
+
+// Gamma function via the Lanczos approximation (g = 7, n = 9). Accurate to
+// about 15 significant digits, which is plenty for L-moment estimators.
+pub fn gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // Reflection formula
+        return std::f64::consts::PI / ((std::f64::consts::PI * x).sin() * gamma(1.0 - x));
+    }
+    let x = x - 1.0;
+    let mut sum = COEFFICIENTS[0];
+    for (i, c) in COEFFICIENTS.iter().enumerate().skip(1) {
+        sum += c / (x + i as f64);
+    }
+    let t = x + 7.5;
+    (2.0 * std::f64::consts::PI).sqrt() * t.powf(x + 0.5) * (-t).exp() * sum
+}
+
+// First three sample L-moments (l1, l2, l3) computed from probability weighted
+// moments, following Hosking (1990).
+pub fn l_moments(values: &[f64]) -> Option<(f64, f64, f64)> {
+    let n = values.len();
+    if n < 3 {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let nf = n as f64;
+    let mut b0 = 0.0;
+    let mut b1 = 0.0;
+    let mut b2 = 0.0;
+    for (i, x) in sorted.iter().enumerate() {
+        let i = i as f64;
+        b0 += x;
+        b1 += x * i / (nf - 1.0);
+        b2 += x * i * (i - 1.0) / ((nf - 1.0) * (nf - 2.0));
+    }
+    b0 /= nf;
+    b1 /= nf;
+    b2 /= nf;
+    Some((b0, 2.0 * b1 - b0, 6.0 * b2 - 6.0 * b1 + b0))
+}
+
+// Minimises `f` with the Nelder-Mead simplex method starting from `start`.
+// `step` gives the initial size of the simplex along each axis. Returns the
+// best point found; callers are expected to return f64::INFINITY for points
+// outside the valid parameter space.
+pub fn nelder_mead<F>(f: F, start: &[f64], step: &[f64], max_iterations: usize) -> Vec<f64>
+where
+    F: Fn(&[f64]) -> f64,
+{
+    let dims = start.len();
+    let mut simplex: Vec<(Vec<f64>, f64)> = Vec::with_capacity(dims + 1);
+    simplex.push((start.to_vec(), f(start)));
+    for i in 0..dims {
+        let mut point = start.to_vec();
+        point[i] += step[i];
+        let value = f(&point);
+        simplex.push((point, value));
+    }
+
+    for _ in 0..max_iterations {
+        simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+        let best = simplex[0].1;
+        let worst = simplex[dims].1;
+        if (worst - best).abs() <= 1e-10 * (best.abs() + 1e-10) {
+            break;
+        }
+
+        // Centroid of everything except the worst point
+        let mut centroid = vec![0.0; dims];
+        for (point, _) in &simplex[..dims] {
+            for (c, p) in centroid.iter_mut().zip(point) {
+                *c += p / dims as f64;
+            }
+        }
+        let towards = |coefficient: f64| -> Vec<f64> {
+            centroid
+                .iter()
+                .zip(&simplex[dims].0)
+                .map(|(c, w)| c + coefficient * (w - c))
+                .collect()
+        };
+
+        let reflected = towards(-1.0);
+        let reflected_value = f(&reflected);
+        if reflected_value < best {
+            let expanded = towards(-2.0);
+            let expanded_value = f(&expanded);
+            simplex[dims] = if expanded_value < reflected_value {
+                (expanded, expanded_value)
+            } else {
+                (reflected, reflected_value)
+            };
+            continue;
+        }
+        if reflected_value < simplex[dims - 1].1 {
+            simplex[dims] = (reflected, reflected_value);
+            continue;
+        }
+        let contracted = towards(0.5);
+        let contracted_value = f(&contracted);
+        if contracted_value < worst {
+            simplex[dims] = (contracted, contracted_value);
+            continue;
+        }
+        // Shrink everything towards the best point
+        let best_point = simplex[0].0.clone();
+        for (point, value) in simplex.iter_mut().skip(1) {
+            for (p, b) in point.iter_mut().zip(&best_point) {
+                *p = b + 0.5 * (*p - b);
+            }
+            *value = f(point);
+        }
+    }
+    simplex.sort_by(|a, b| a.1.total_cmp(&b.1));
+    simplex.swap_remove(0).0
+}
+
+pub fn median(values: &[f64]) -> f64 {
+    quantile(values, 0.5)
+}
+
+// Error function, Abramowitz and Stegun 7.1.26 (absolute error below 1.5e-7)
+pub fn erf(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.327_591_1 * x.abs());
+    let poly = t * (0.254_829_592 + t * (-0.284_496_736 + t * (1.421_413_741 + t * (-1.453_152_027 + t * 1.061_405_429))));
+    let y = 1.0 - poly * (-x * x).exp();
+    if x >= 0.0 {
+        y
+    } else {
+        -y
+    }
+}
+
+// Cumulative distribution function of the standard normal distribution
+pub fn normal_cdf(x: f64) -> f64 {
+    0.5 * (1.0 + erf(x / std::f64::consts::SQRT_2))
+}
+
+// Inverse of `normal_cdf`, using Acklam's rational approximation (relative
+// error below 1.2e-9).
+pub fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [-3.969_683_028_665_376e1, 2.209_460_984_245_205e2, -2.759_285_104_469_687e2, 1.383_577_518_672_69e2, -3.066_479_806_614_716e1, 2.506_628_277_459_239];
+    const B: [f64; 5] = [-5.447_609_879_822_406e1, 1.615_858_368_580_409e2, -1.556_989_798_598_866e2, 6.680_131_188_771_972e1, -1.328_068_155_288_572e1];
+    const C: [f64; 6] = [-7.784_894_002_430_293e-3, -3.223_964_580_411_365e-1, -2.400_758_277_161_838, -2.549_732_539_343_734, 4.374_664_141_464_968, 2.938_163_982_698_783];
+    const D: [f64; 4] = [7.784_695_709_041_462e-3, 3.224_671_290_700_398e-1, 2.445_134_137_142_996, 3.754_408_661_907_416];
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if p >= 1.0 {
+        return f64::INFINITY;
+    }
+    if p < 0.02425 {
+        return tail((-2.0 * p.ln()).sqrt());
+    }
+    if p > 1.0 - 0.02425 {
+        return -tail((-2.0 * (1.0 - p).ln()).sqrt());
+    }
+    let q = p - 0.5;
+    let r = q * q;
+    (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+        / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+}
+
+// Sample autocorrelation at `lag`, using the usual biased estimator
+pub fn autocorrelation(values: &[f64], lag: usize) -> f64 {
+    let n = values.len();
+    if lag >= n {
+        return f64::NAN;
+    }
+    let m = mean(values);
+    let denominator: f64 = values.iter().map(|v| (v - m) * (v - m)).sum();
+    let numerator: f64 = (0..n - lag).map(|i| (values[i] - m) * (values[i + lag] - m)).sum();
+    numerator / denominator
+}
+
+// Deterministic pseudo-random numbers (SplitMix64) for simulations which
+// must give the same answer every run.
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in (0, 1), never exactly 0 or 1
+    pub fn uniform(&mut self) -> f64 {
+        ((self.next_u64() >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    }
+
+    // Standard normal, by inverting the CDF
+    pub fn normal(&mut self) -> f64 {
+        normal_quantile(self.uniform())
+    }
+}
+
+// Shape of the bump kde() puts at every value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kernel {
+    Gaussian,
+    Epanechnikov, // 3/4 (1 - u²) for |u| <= 1
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bandwidth {
+    Silverman, // 0.9 min(σ, IQR / 1.34) n^-1/5
+    Scott, // 1.06 σ n^-1/5
+    Fixed(f64),
+}
+
+impl Kernel {
+    fn weight(&self, u: f64) -> f64 {
+        match self {
+            Kernel::Gaussian => (-0.5 * u * u).exp() / (2.0 * std::f64::consts::PI).sqrt(),
+            Kernel::Epanechnikov if u.abs() <= 1.0 => 0.75 * (1.0 - u * u),
+            Kernel::Epanechnikov => 0.0,
+        }
+    }
+
+    // Distance in bandwidths beyond which the kernel is (close enough to) zero
+    fn support(&self) -> f64 {
+        match self {
+            Kernel::Gaussian => 4.0,
+            Kernel::Epanechnikov => 1.0,
+        }
+    }
+
+    // The rules of thumb give a Gaussian bandwidth. An Epanechnikov kernel
+    // needs a wider one to smooth as much (ratio of canonical bandwidths).
+    fn scale(&self) -> f64 {
+        match self {
+            Kernel::Gaussian => 1.0,
+            Kernel::Epanechnikov => 2.214,
+        }
+    }
+}
+
+// Weighted mean and variance (with the effective sample size correction).
+// With all the weight on a single value there is no spread to correct, so
+// the variance is zero.
+pub fn weighted_mean_variance(values: &[(f64, f64)]) -> (f64, f64) {
+    let total: f64 = values.iter().map(|v| v.1).sum();
+    let squares: f64 = values.iter().map(|v| v.1 * v.1).sum();
+    if total <= 0.0 {
+        return (f64::NAN, f64::NAN);
+    }
+    let mean = values.iter().map(|v| v.0 * v.1).sum::<f64>() / total;
+    let correction = 1.0 - squares / (total * total);
+    if correction <= 0.0 {
+        return (mean, 0.0);
+    }
+    let spread = values.iter().map(|v| (v.0 - mean).powi(2) * v.1).sum::<f64>() / total;
+    (mean, spread / correction)
+}
+
+pub fn effective_sample_size(values: &[(f64, f64)]) -> f64 {
+    let total: f64 = values.iter().map(|v| v.1).sum();
+    let squares: f64 = values.iter().map(|v| v.1 * v.1).sum();
+    total * total / squares
+}
+
+// Bandwidth for kde() by one of the rules of thumb. These assume a roughly
+// normal distribution and use Kish's effective sample size, (Σw)² / Σw², in
+// place of n so that a few long readings don't count as many independent ones.
+pub fn bandwidth(values: &[(f64, f64)], rule: Bandwidth, kernel: Kernel) -> f64 {
+    let n = effective_sample_size(values);
+    let sd = weighted_mean_variance(values).1.sqrt();
+    let gaussian = match rule {
+        Bandwidth::Fixed(width) => return width,
+        Bandwidth::Scott => 1.06 * sd * n.powf(-0.2),
+        Bandwidth::Silverman => {
+            let iqr = weighted_quantile(values, 0.75) - weighted_quantile(values, 0.25);
+            let spread = if iqr > 0.0 { sd.min(iqr / 1.34) } else { sd };
+            0.9 * spread * n.powf(-0.2)
+        }
+    };
+    gaussian * kernel.scale()
+}
+
+// Kernel density estimation of (value, weight) pairs, eg. temperatures weighted
+// by how long they lasted: the estimated density at each of `points`
+pub fn kde(values: &[(f64, f64)], kernel: Kernel, bandwidth: f64, points: &[f64]) -> Vec<f64> {
+    // Readings are in tenths of a degree so there are far fewer distinct
+    // values than readings; merge them before summing the kernels.
+    let mut merged: Vec<(f64, f64)> = Vec::new();
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+    for (value, weight) in sorted {
+        match merged.last_mut() {
+            Some(last) if last.0 == value => last.1 += weight,
+            _ => merged.push((value, weight)),
+        }
+    }
+    let total: f64 = merged.iter().map(|v| v.1).sum();
+    if total <= 0.0 || bandwidth <= 0.0 {
+        return vec![f64::NAN; points.len()];
+    }
+    let reach = kernel.support() * bandwidth;
+    points
+        .iter()
+        .map(|&x| {
+            let start = merged.partition_point(|v| v.0 < x - reach);
+            let end = merged.partition_point(|v| v.0 <= x + reach);
+            merged[start..end].iter().map(|&(v, w)| w * kernel.weight((x - v) / bandwidth)).sum::<f64>() / (total * bandwidth)
+        })
+        .collect()
+}
+
+// Density on an even grid of `steps` + 1 points running from a kernel's width
+// below the lowest value to a kernel's width above the highest: (x, density)
+pub fn kde_curve(values: &[(f64, f64)], kernel: Kernel, rule: Bandwidth, steps: usize) -> Vec<(f64, f64)> {
+    let width = bandwidth(values, rule, kernel);
+    let low = values.iter().map(|v| v.0).fold(f64::INFINITY, f64::min);
+    let high = values.iter().map(|v| v.0).fold(f64::NEG_INFINITY, f64::max);
+    if !low.is_finite() || width.is_nan() || width <= 0.0 {
+        return Vec::new();
+    }
+    let (low, high) = (low - kernel.support() * width, high + kernel.support() * width);
+    let points: Vec<f64> = (0..=steps).map(|i| low + (high - low) * i as f64 / steps as f64).collect();
+    let density = kde(values, kernel, width, &points);
+    points.into_iter().zip(density).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weighted_variance_of_a_single_value() {
+        assert_eq!(weighted_mean_variance(&[(21.5, 60.0)]), (21.5, 0.0));
+        assert_eq!(weighted_mean_variance(&[(21.5, 60.0), (30.0, 0.0)]), (21.5, 0.0));
+        // Equal weights give the usual sample variance
+        let (mean, variance) = weighted_mean_variance(&[(1.0, 5.0), (2.0, 5.0), (3.0, 5.0)]);
+        assert!((mean - 2.0).abs() < 1e-12 && (variance - 1.0).abs() < 1e-12);
+        // No spread means no bandwidth, so no curve rather than NaNs
+        assert_eq!(bandwidth(&[(21.5, 60.0)], Bandwidth::Silverman, Kernel::Gaussian), 0.0);
+        assert!(kde_curve(&[(21.5, 60.0)], Kernel::Gaussian, Bandwidth::Scott, 100).is_empty());
+    }
+
+    #[test]
+    fn kde_integrates_to_one() {
+        // Readings of different lengths, rounded to tenths like ISD
+        let mut random = Random::new(1);
+        let values: Vec<(f64, f64)> =
+            (0..2000).map(|i| (((20.0 + 4.0 * random.normal()) * 10.0).round() / 10.0, if i % 3 == 0 { 20.0 } else { 60.0 })).collect();
+        for kernel in [Kernel::Gaussian, Kernel::Epanechnikov] {
+            for rule in [Bandwidth::Silverman, Bandwidth::Scott, Bandwidth::Fixed(1.5)] {
+                let curve = kde_curve(&values, kernel, rule, 2000);
+                let area: f64 = curve.windows(2).map(|w| (w[1].0 - w[0].0) * (w[0].1 + w[1].1) / 2.0).sum();
+                assert!((area - 1.0).abs() < 1e-3, "{:?} {:?}: {}", kernel, rule, area);
+            }
+        }
+    }
+
+    #[test]
+    fn kernels_and_rules_match_their_formulas() {
+        // A single reading: the density is the kernel itself, scaled by the bandwidth
+        let gaussian = kde(&[(10.0, 1.0)], Kernel::Gaussian, 2.0, &[10.0, 12.0]);
+        assert!((gaussian[0] - 0.199471).abs() < 1e-6 && (gaussian[1] - 0.120985).abs() < 1e-6);
+        let epanechnikov = kde(&[(10.0, 1.0)], Kernel::Epanechnikov, 2.0, &[10.0, 11.0, 12.5]);
+        assert_eq!(epanechnikov, vec![0.375, 0.28125, 0.0]);
+        // 1, 2, 3, 4, 5 equally weighted: σ = √2.5 = 1.5811 and n^-1/5 = 0.72478.
+        // Scott: 1.06 × 1.5811 × 0.72478 = 1.21474. The quartiles are 2 and 4,
+        // so Silverman uses IQR / 1.34 = 1.49254 < σ: 0.9 × 1.49254 × 0.72478 = 0.97358.
+        let values: Vec<(f64, f64)> = (1..=5).map(|v| (v as f64, 1.0)).collect();
+        assert!((bandwidth(&values, Bandwidth::Scott, Kernel::Gaussian) - 1.214736).abs() < 1e-6);
+        assert!((bandwidth(&values, Bandwidth::Silverman, Kernel::Gaussian) - 0.973585).abs() < 1e-6);
+        assert!((bandwidth(&values, Bandwidth::Scott, Kernel::Epanechnikov) - 1.214736 * 2.214).abs() < 1e-5);
+    }
+}
+
@@ -0,0 +1,287 @@
+use crate::chart::{self, Axis, ChartSpec, Series, SeriesData};
+use crate::daily;
+use crate::stats;
+use crate::units;
+use crate::TempData;
+
+/*
+Seasonal-trend decomposition using LOESS (STL, Cleveland et al. 1990) of a
+station's daily mean temperatures:
+    value = seasonal + trend + remainder
+Rather than assume a fixed period, the cycle-subseries are the calendar days
+(every January 1st, every January 2nd, ...), so leap years don't slowly shift
+the seasonal cycle. Missing days are given zero weight in every smoother, so
+they still get seasonal and trend values but no remainder.
+
+The remainder is the day to day "weather noise" once the normal seasonal cycle
+and the slow climate trend are taken out, and is what the strangeness metrics
+work on.
+*/
+
+#[derive(Clone, Copy, Debug)]
+pub struct StlParameters {
+    pub seasonal_window: usize, // Years, odd. Larger means a more stable seasonal cycle
+    pub trend_window: usize, // Days, odd
+    pub low_pass_window: usize, // Days, odd
+    pub inner_iterations: usize,
+    pub robust_iterations: usize, // Outer loop, 0 for the non robust version
+}
+
+// Period of the seasonal cycle in days
+const PERIOD: usize = 365;
+
+impl Default for StlParameters {
+    fn default() -> StlParameters {
+        let seasonal_window = 7;
+        // Cleveland's recommendation: the smallest odd number greater than
+        // 1.5 * period / (1 - 1.5 / seasonal window)
+        let trend_window = (1.5 * PERIOD as f64 / (1.0 - 1.5 / seasonal_window as f64)).ceil() as usize;
+        StlParameters {
+            seasonal_window,
+            trend_window: trend_window | 1,
+            low_pass_window: PERIOD | 1,
+            inner_iterations: 2,
+            robust_iterations: 1,
+        }
+    }
+}
+
+pub struct Decomposition {
+    pub first_year: u32,
+    pub values: Vec<Option<f64>>, // One per day from January 1st of `first_year`
+    pub seasonal: Vec<f64>,
+    pub trend: Vec<f64>,
+    pub remainder: Vec<Option<f64>>,
+}
+
+// Daily means of every year joined into one series, with one entry per day
+// that actually exists (365 or 366 per year). Also returns the calendar day
+// index of every entry.
+pub fn daily_mean_series(years: &[Vec<TempData>], first_year: u32) -> (Vec<Option<f64>>, Vec<usize>) {
+    let mut values = Vec::new();
+    let mut day_indices = Vec::new();
+    for (i, temps) in years.iter().enumerate() {
+        let days_in_year = if daily::is_leap_year(first_year + i as u32) { 366 } else { 365 };
+        for (day, stats) in daily::daily_stats(temps).iter().enumerate().take(days_in_year) {
+            values.push(stats.map(|s| s.mean));
+            day_indices.push(daily::calendar_day(first_year + i as u32, day));
+        }
+    }
+    (values, day_indices)
+}
+
+// Locally weighted linear regression of `y` (at positions `x`) evaluated at
+// every position in `at`, using the `window` nearest points and tricube
+// weights multiplied by `weights`.
+fn loess(x: &[f64], y: &[f64], weights: &[f64], at: &[f64], window: usize) -> Vec<f64> {
+    let n = x.len();
+    let requested = window.max(2);
+    let window = requested.min(n);
+    let mut start = 0;
+    at.iter()
+        .map(|&target| {
+            if n == 0 {
+                return f64::NAN;
+            }
+            // Slide the window of nearest points along; `x` and `at` are sorted
+            while start + window < n && target - x[start] > x[start + window] - target {
+                start += 1;
+            }
+            let end = (start + window).min(n);
+            let max_distance = x[start..end].iter().map(|xi| (xi - target).abs()).fold(0.0, f64::max);
+            // Cleveland widens the bandwidth when asked for more points than exist
+            let bandwidth = max_distance * (requested as f64 / window as f64).max(1.0) * 1.0001;
+            let (mut sw, mut swx, mut swy, mut swxx, mut swxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for i in start..end {
+                let u = (x[i] - target).abs() / bandwidth.max(1e-12);
+                let w = (1.0 - u * u * u).max(0.0).powi(3) * weights[i];
+                let dx = x[i] - target;
+                sw += w;
+                swx += w * dx;
+                swy += w * y[i];
+                swxx += w * dx * dx;
+                swxy += w * dx * y[i];
+            }
+            if sw <= 0.0 {
+                return f64::NAN;
+            }
+            let determinant = sw * swxx - swx * swx;
+            if determinant.abs() < 1e-12 * sw * sw.max(1.0) {
+                swy / sw
+            } else {
+                // Intercept of the local line, since positions are relative to the target
+                (swxx * swy - swx * swxy) / determinant
+            }
+        })
+        .collect()
+}
+
+// Centred moving average which shrinks at the ends of the series
+fn moving_average(values: &[f64], window: usize) -> Vec<f64> {
+    let half = window / 2;
+    let mut prefix = vec![0.0];
+    for v in values {
+        prefix.push(prefix.last().unwrap() + v);
+    }
+    (0..values.len())
+        .map(|i| {
+            let start = i.saturating_sub(half);
+            let end = (i + window - half).min(values.len());
+            (prefix[end] - prefix[start]) / (end - start) as f64
+        })
+        .collect()
+}
+
+// STL's low pass filter: moving averages of one period, one period and three
+// days. The cycle is padded with a copy of its first and last periods so the
+// averages at the ends still cover a whole seasonal cycle.
+fn low_pass_filter(cycle: &[f64]) -> Vec<f64> {
+    let n = cycle.len();
+    if n < PERIOD {
+        return moving_average(&moving_average(&moving_average(cycle, PERIOD), PERIOD), 3);
+    }
+    let mut padded = Vec::with_capacity(n + 2 * PERIOD);
+    padded.extend_from_slice(&cycle[..PERIOD]);
+    padded.extend_from_slice(cycle);
+    padded.extend_from_slice(&cycle[n - PERIOD..]);
+    let filtered = moving_average(&moving_average(&moving_average(&padded, PERIOD), PERIOD), 3);
+    filtered[PERIOD..PERIOD + n].to_vec()
+}
+
+pub fn decompose(years: &[Vec<TempData>], first_year: u32, parameters: &StlParameters) -> Decomposition {
+    let (values, day_indices) = daily_mean_series(years, first_year);
+    let n = values.len();
+    let positions: Vec<f64> = (0..n).map(|i| i as f64).collect();
+    let y: Vec<f64> = values.iter().map(|v| v.unwrap_or(0.0)).collect();
+    let present: Vec<f64> = values.iter().map(|v| if v.is_some() { 1.0 } else { 0.0 }).collect();
+
+    // Positions of every calendar day, ie. the cycle-subseries
+    let mut subseries: Vec<Vec<usize>> = vec![Vec::new(); 366];
+    for (i, &day) in day_indices.iter().enumerate() {
+        subseries[day].push(i);
+    }
+
+    let mut robustness = vec![1.0; n];
+    let mut seasonal = vec![0.0; n];
+    let mut trend = vec![0.0; n];
+    for outer in 0..=parameters.robust_iterations {
+        let weights: Vec<f64> = present.iter().zip(&robustness).map(|(p, r)| p * r).collect();
+        for _ in 0..parameters.inner_iterations {
+            // Smooth each calendar day across the years
+            let detrended: Vec<f64> = y.iter().zip(&trend).map(|(v, t)| v - t).collect();
+            let mut cycle = vec![0.0; n];
+            for indices in subseries.iter().filter(|s| !s.is_empty()) {
+                let x: Vec<f64> = (0..indices.len()).map(|i| i as f64).collect();
+                let sub_y: Vec<f64> = indices.iter().map(|&i| detrended[i]).collect();
+                let sub_w: Vec<f64> = indices.iter().map(|&i| weights[i]).collect();
+                let smoothed = loess(&x, &sub_y, &sub_w, &x, parameters.seasonal_window);
+                // When every year of a day has zero weight, Cleveland's
+                // stless keeps the data as it is
+                for (&i, s) in indices.iter().zip(smoothed) {
+                    cycle[i] = if !s.is_nan() {
+                        s
+                    } else if present[i] > 0.0 {
+                        detrended[i]
+                    } else {
+                        0.0
+                    };
+                }
+            }
+            // Remove any low frequency power which leaked into the cycle
+            let low_pass = low_pass_filter(&cycle);
+            let low_pass = loess(&positions, &low_pass, &vec![1.0; n], &positions, parameters.low_pass_window);
+            seasonal = cycle.iter().zip(&low_pass).map(|(c, l)| c - l).collect();
+
+            let deseasonalised: Vec<f64> = y.iter().zip(&seasonal).map(|(v, s)| v - s).collect();
+            trend = loess(&positions, &deseasonalised, &weights, &positions, parameters.trend_window);
+        }
+        if outer < parameters.robust_iterations {
+            // Bisquare weights so that outliers don't drag the components around
+            let residuals: Vec<f64> = (0..n)
+                .filter(|&i| present[i] > 0.0)
+                .map(|i| (y[i] - seasonal[i] - trend[i]).abs())
+                .collect();
+            let h = 6.0 * stats::median(&residuals);
+            robustness = (0..n)
+                .map(|i| {
+                    let u = (y[i] - seasonal[i] - trend[i]).abs() / h.max(1e-12);
+                    (1.0 - u * u).max(0.0).powi(2)
+                })
+                .collect();
+        }
+    }
+
+    let remainder = (0..n).map(|i| values[i].map(|v| v - seasonal[i] - trend[i])).collect();
+    Decomposition { first_year, values, seasonal, trend, remainder }
+}
+
+// The daily means with the trend, and the trend plus the seasonal cycle, drawn
+// over them. Time runs in years from January 1st of the first year.
+pub fn decomposition_chart(decomposition: &Decomposition) -> ChartSpec {
+    let time = |day: usize| decomposition.first_year as f64 + day as f64 / 365.2425;
+    let line = |values: Vec<f64>| values.into_iter().enumerate().map(|(day, v)| (time(day), units::temperature(v))).collect();
+    let means: Vec<(f64, f64)> =
+        decomposition.values.iter().enumerate().filter_map(|(day, v)| Some((time(day), units::temperature((*v)?)))).collect();
+    let seasonal_trend = decomposition.seasonal.iter().zip(&decomposition.trend).map(|(s, t)| s + t).collect();
+
+    let x = Axis { range: time(0)..time(decomposition.values.len()), description: "Year".to_string(), labels: 12, formatter: |year| format!("{:.0}", year) };
+    let mut spec = ChartSpec::new(x, chart::temperature_axis(-15.0..40.0));
+    spec.series.push(Series { name: "Daily mean".to_string(), color: chart::PALETTE[0], data: SeriesData::Scatter { points: means, radius: 2 } });
+    spec.series.push(Series {
+        name: "Seasonal + trend".to_string(),
+        color: chart::PALETTE[1],
+        data: SeriesData::Line { points: line(seasonal_trend), max_gap: None },
+    });
+    spec.series.push(Series {
+        name: "Trend".to_string(),
+        color: chart::PALETTE[3],
+        data: SeriesData::Line { points: line(decomposition.trend.clone()), max_gap: None },
+    });
+    spec.legend = true;
+    spec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Tenths;
+
+    // A sine over the calendar year plus a linear warming of 0.3 °C per year,
+    // one reading per day held all day. `missing` days have no reading.
+    fn sine_and_trend(first_year: u32, num_years: u32, missing: &[usize]) -> (Vec<Vec<TempData>>, Vec<(f64, f64)>) {
+        let mut years = Vec::new();
+        let mut truth = Vec::new();
+        for year in first_year..first_year + num_years {
+            let days = if daily::is_leap_year(year) { 366 } else { 365 };
+            let mut temps = Vec::new();
+            for day in 0..days {
+                let seasonal = 8.0 * (2.0 * std::f64::consts::PI * daily::calendar_day(year, day) as f64 / 366.0).sin();
+                let trend = 15.0 + 0.3 * (truth.len() as f64 / 365.25);
+                if !missing.contains(&truth.len()) {
+                    let temp10 = Tenths::from_degrees(seasonal + trend);
+                    temps.push(TempData { temp10, duration: 1440, minute_of_year: day as u32 * 1440 });
+                }
+                truth.push((seasonal, trend));
+            }
+            years.push(temps);
+        }
+        (years, truth)
+    }
+
+    #[test]
+    fn separates_a_sine_from_a_linear_trend() {
+        let missing = [400, 401, 402, 1500];
+        let (years, truth) = sine_and_trend(2012, 10, &missing);
+        let decomposition = decompose(&years, 2012, &StlParameters::default());
+        assert_eq!(decomposition.values.len(), truth.len());
+        for (i, (seasonal, trend)) in truth.iter().enumerate() {
+            assert!((decomposition.trend[i] - trend).abs() < 0.2, "day {}: trend {} against {}", i, decomposition.trend[i], trend);
+            assert!((decomposition.seasonal[i] - seasonal).abs() < 0.2, "day {}: seasonal {} against {}", i, decomposition.seasonal[i], seasonal);
+            match decomposition.remainder[i] {
+                // Readings are rounded to tenths
+                Some(remainder) => assert!(remainder.abs() < 0.25, "day {}: remainder {}", i, remainder),
+                None => assert!(missing.contains(&i)),
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::humidity::{self, DerivedTemperature, Observation};
+use crate::precipitation::PrecipitationStats;
+use crate::stats;
+use crate::stl;
+use crate::units;
+use crate::wind::WindStats;
+use crate::TempData;
+
+/*
+Strangeness metrics. Each metric is one axis along which a station's weather
+can be strange, and stations are compared axis by axis. The temperature
+metrics work on the STL remainder (see stl.rs), ie. the weather noise left
+once the seasonal cycle and long term trend have been taken out, so that a
+place isn't called strange just for being hot or for warming. Spectral
+metrics (how strong the daily and yearly cycles are) come from
+spectral::spectral_metrics(), wind metrics from wind::wind_stats(),
+precipitation metrics from precipitation::precipitation_stats(), and
+humidity metrics from the observations in humidity.rs. Dew point and the
+derived temperatures go through stl.rs and noise_metrics() like the air
+temperature (see derived_noise_metrics()).
+*/
+
+#[derive(Clone, Debug)]
+pub struct Metric {
+    pub name: String,
+    pub value: f64,
+    pub unit: &'static str,
+}
+
+// Metrics describing the weather noise (STL remainder, °C) of a station. The
+// values are in the unit chosen with units::set_unit().
+pub fn noise_metrics(remainder: &[Option<f64>]) -> Vec<Metric> {
+    let values: Vec<f64> = remainder.iter().flatten().copied().collect();
+    let mean = stats::mean(&values);
+    let sd = stats::variance(&values).sqrt();
+    let fourth_moment = values.iter().map(|v| ((v - mean) / sd).powi(4)).sum::<f64>() / values.len() as f64;
+    let skewness = values.iter().map(|v| ((v - mean) / sd).powi(3)).sum::<f64>() / values.len() as f64;
+    let beyond_two_sigma = values.iter().filter(|v| (*v - mean).abs() > 2.0 * sd).count() as f64 / values.len() as f64;
+    // Persistence uses consecutive days only, so gaps don't pair up unrelated days
+    let pairs: Vec<(f64, f64)> = remainder
+        .windows(2)
+        .filter_map(|w| Some((w[0]?, w[1]?)))
+        .collect();
+    let lag1 = pairs.iter().map(|(a, b)| (a - mean) * (b - mean)).sum::<f64>() / (pairs.len() as f64 * sd * sd);
+    let mean_abs_change = pairs.iter().map(|(a, b)| (b - a).abs()).sum::<f64>() / pairs.len() as f64;
+    vec![
+        Metric { name: "Noise standard deviation".to_string(), value: units::difference(sd), unit: units::symbol() },
+        Metric { name: "Noise skewness".to_string(), value: skewness, unit: "" },
+        Metric { name: "Noise excess kurtosis".to_string(), value: fourth_moment - 3.0, unit: "" },
+        Metric { name: "Days beyond 2σ".to_string(), value: beyond_two_sigma * 100.0, unit: "%" },
+        Metric { name: "Noise lag-1 autocorrelation".to_string(), value: lag1, unit: "" },
+        Metric { name: "Mean |Δ noise| day to day".to_string(), value: units::difference(mean_abs_change), unit: units::symbol() },
+    ]
+}
+
+// noise_metrics() of each derived temperature, named after it. `years` are
+// the observations of each year from first_year on.
+pub fn derived_noise_metrics(years: &[Vec<Observation>], first_year: u32) -> Vec<Metric> {
+    [
+        (DerivedTemperature::DewPoint, "Dew point"),
+        (DerivedTemperature::HeatIndex, "Heat index"),
+        (DerivedTemperature::WindChill, "Wind chill"),
+        (DerivedTemperature::ApparentTemperature, "Apparent temperature"),
+    ]
+    .iter()
+    .flat_map(|&(variable, label)| {
+        let temps: Vec<Vec<TempData>> = years.iter().map(|observations| humidity::derived_temps(observations, variable)).collect();
+        let noise = stl::decompose(&temps, first_year, &stl::StlParameters::default());
+        noise_metrics(&noise.remainder).into_iter().map(move |metric| Metric {
+            name: format!("{} {}{}", label, metric.name[..1].to_lowercase(), &metric.name[1..]),
+            ..metric
+        })
+    })
+    .collect()
+}
+
+// Dew point at or above which the air feels oppressive, °C
+const OPPRESSIVE_DEW_POINT: f64 = 20.0;
+// Heat index at or above which the NWS warns of danger (105 °F), °C
+const DANGEROUS_HEAT_INDEX: f64 = 40.6;
+// Air temperature from which the heat index is worth looking at (80 °F), °C
+const HEAT_INDEX_FROM: f64 = 26.7;
+
+// Metrics describing how humid a station is and how much that changes how
+// the heat feels. Shares are of the time covered by the observations.
+pub fn humidity_metrics(observations: &[Observation]) -> Vec<Metric> {
+    let minutes = |filter: &dyn Fn(&Observation) -> bool| {
+        observations.iter().filter(|o| filter(o)).fold(0.0, |total, o| total + o.duration as f64)
+    };
+    let total = minutes(&|_| true).max(1.0);
+    let weighted_mean = |value: &dyn Fn(&Observation) -> f64| {
+        observations.iter().map(|o| value(o) * o.duration as f64).sum::<f64>() / total
+    };
+    let hot = |o: &Observation| o.temp >= HEAT_INDEX_FROM;
+    let hot_minutes = minutes(&hot).max(1.0);
+    let heat_index_excess = observations
+        .iter()
+        .filter(|o| hot(o))
+        .filter_map(|o| Some((o.get(DerivedTemperature::HeatIndex)? - o.temp) * o.duration as f64))
+        .sum::<f64>()
+        / hot_minutes;
+    let dangerous = |o: &Observation| o.get(DerivedTemperature::HeatIndex).is_some_and(|hi| hi >= DANGEROUS_HEAT_INDEX);
+    vec![
+        Metric { name: "Mean relative humidity".to_string(), value: weighted_mean(&|o| o.relative_humidity()), unit: "%" },
+        Metric { name: "Mean dew point".to_string(), value: units::temperature(weighted_mean(&|o| o.dew_point)), unit: units::symbol() },
+        Metric { name: "Oppressive dew point".to_string(), value: minutes(&|o| o.dew_point >= OPPRESSIVE_DEW_POINT) / total * 100.0, unit: "%" },
+        Metric { name: "Heat index excess when hot".to_string(), value: units::difference(heat_index_excess), unit: units::symbol() },
+        Metric { name: "Dangerous heat index".to_string(), value: minutes(&dangerous) / total * 100.0, unit: "%" },
+    ]
+}
+
+// Metrics describing how windy a station is. Gust metrics are left out when
+// the station never reports gusts.
+pub fn wind_metrics(wind: &WindStats) -> Vec<Metric> {
+    let mut metrics = vec![
+        Metric { name: "Mean wind speed".to_string(), value: wind.mean_speed, unit: "m/s" },
+        Metric { name: "Calm".to_string(), value: wind.calm, unit: "%" },
+        Metric { name: "Wind direction steadiness".to_string(), value: wind.steadiness, unit: "" },
+    ];
+    if let Some(gusts) = wind.gusts {
+        metrics.push(Metric { name: "Days with gusts".to_string(), value: gusts.days as f64, unit: "" });
+        metrics.push(Metric { name: "95th percentile gust".to_string(), value: gusts.percentile_95, unit: "m/s" });
+        metrics.push(Metric { name: "Gust factor".to_string(), value: gusts.gust_factor, unit: "" });
+    }
+    metrics
+}
+
+pub fn precipitation_metrics(precipitation: &PrecipitationStats) -> Vec<Metric> {
+    let mut metrics = vec![
+        Metric { name: "Wet day frequency".to_string(), value: precipitation.wet_day_frequency, unit: "%" },
+        Metric { name: "Mean wet day precipitation".to_string(), value: precipitation.mean_intensity, unit: "mm" },
+    ];
+    if let Some(&(_, depth)) = precipitation.intensity_percentiles.iter().find(|(p, _)| *p == 95.0) {
+        metrics.push(Metric { name: "95th percentile wet day".to_string(), value: depth, unit: "mm" });
+    }
+    if let Some(total) = precipitation.mean_annual_total {
+        metrics.push(Metric { name: "Mean annual precipitation".to_string(), value: total, unit: "mm" });
+    }
+    if let Some(cv) = precipitation.annual_variability {
+        metrics.push(Metric { name: "Annual precipitation variability".to_string(), value: cv, unit: "%" });
+    }
+    if let Some(spell) = precipitation.longest_dry {
+        metrics.push(Metric { name: "Longest dry spell".to_string(), value: spell.days as f64, unit: "d" });
+    }
+    if let Some(spell) = precipitation.longest_wet {
+        metrics.push(Metric { name: "Longest wet spell".to_string(), value: spell.days as f64, unit: "d" });
+    }
+    metrics
+}
+
+// Prints every metric for every station side by side. The rows are the
+// metrics of the first station (usually Austin).
+pub fn print_comparison(stations: &[(&str, Vec<Metric>)]) {
+    let Some((_, reference)) = stations.first() else {
+        return;
+    };
+    print!("{:<50}", "Metric");
+    for (name, _) in stations {
+        print!("{:>14}", name);
+    }
+    println!();
+    for metric in reference {
+        print!("{:<50}", metric.name);
+        for (_, metrics) in stations {
+            match metrics.iter().find(|m| m.name == metric.name) {
+                Some(m) => print!("{:>11.2} {:<2}", m.value, m.unit),
+                None => print!("{:>14}", "-"),
+            }
+        }
+        println!();
+    }
+}
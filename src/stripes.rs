@@ -0,0 +1,154 @@
+use plotters::prelude::*;
+
+use crate::chart::{self, Axis, ChartSpec, ColourMap, Grid, Series, SeriesData, Theme};
+use crate::stats;
+use crate::trend::{self, SeriesPoint, TrendResult};
+use crate::units;
+
+/*
+Warming stripes and the matching bar chart, both from an annual series such
+as trend::aggregate_series(.., Aggregate::Annual, ..):
+    - WarmingStripes: one vertical stripe per year coloured by its anomaly
+      from the mean of the whole series, and nothing else.
+    - AnomalyBars: the same anomalies as bars in the same colours, with the
+      Sen's slope trend line and its value in the legend.
+Years missing from the series are left as gaps. Following Ed Hawkins, the
+colour scale saturates at 2.6 standard deviations of the anomalies unless a
+limit is given.
+*/
+
+const DEFAULT_LIMIT_SIGMAS: f64 = 2.6;
+
+fn default_limit(anomalies: &[SeriesPoint]) -> f64 {
+    let values: Vec<f64> = anomalies.iter().map(|p| p.1).collect();
+    let sd = stats::variance(&values).sqrt();
+    if sd > 0.0 {
+        DEFAULT_LIMIT_SIGMAS * sd
+    } else {
+        1.0
+    }
+}
+
+// Years covered by an annual series, whose times are mid-year (eg. 2022.5)
+fn year_range(anomalies: &[SeriesPoint]) -> (i32, i32) {
+    let first = anomalies.iter().map(|p| p.0.floor() as i32).min().unwrap_or(0);
+    let last = anomalies.iter().map(|p| p.0.floor() as i32).max().unwrap_or(0);
+    (first, last + 1)
+}
+
+pub struct WarmingStripes {
+    pub anomalies: Vec<SeriesPoint>, // °C
+    pub limit: f64, // °C, anomalies beyond ±limit get the end colours
+    pub colour_map: ColourMap,
+    pub title: Option<String>,
+    pub size: (u32, u32),
+}
+
+impl WarmingStripes {
+    pub fn new(annual: &[SeriesPoint]) -> WarmingStripes {
+        let anomalies = trend::anomalies(annual);
+        WarmingStripes { limit: default_limit(&anomalies), anomalies, colour_map: chart::STRIPES, title: None, size: (1920, 600) }
+    }
+}
+
+impl WarmingStripes {
+    pub fn spec(&self) -> ChartSpec {
+        let (first, last) = year_range(&self.anomalies);
+        let cells = self
+            .anomalies
+            .iter()
+            .map(|&(time, anomaly)| {
+                let year = time.floor();
+                (year, 0.0, year + 1.0, 1.0, self.colour_map.diverging(anomaly, self.limit))
+            })
+            .collect();
+        let mut spec = ChartSpec::new(Axis::new(first as f64..last as f64, "Year"), Axis::new(0.0..1.0, ""));
+        spec.title = self.title.clone();
+        spec.size = self.size;
+        // Grey rather than white so that years without data don't look like
+        // years with no anomaly
+        spec.theme = Theme { background: RGBColor(128, 128, 128), foreground: BLACK, font_size: 70, margin: 0 };
+        spec.axes = false;
+        spec.series.push(Series { name: String::new(), color: BLACK, data: SeriesData::Cells { cells } });
+        spec
+    }
+}
+
+pub struct AnomalyBars {
+    pub anomalies: Vec<SeriesPoint>, // °C
+    pub limit: f64,
+    pub colour_map: ColourMap,
+    pub trend: Option<TrendResult>,
+    pub title: Option<String>,
+    pub theme: Theme,
+}
+
+impl AnomalyBars {
+    pub fn new(annual: &[SeriesPoint]) -> AnomalyBars {
+        let anomalies = trend::anomalies(annual);
+        AnomalyBars {
+            limit: default_limit(&anomalies),
+            trend: trend::mann_kendall(&anomalies, true, 0.95),
+            anomalies,
+            colour_map: chart::STRIPES,
+            title: None,
+            theme: Theme::dark(),
+        }
+    }
+}
+
+// Line through the series with Sen's slope, placed by the median of the
+// residuals: (start, end) points
+fn trend_line(anomalies: &[SeriesPoint], trend: &TrendResult) -> [(f64, f64); 2] {
+    let slope = trend.slope_per_decade / 10.0;
+    let residuals: Vec<f64> = anomalies.iter().map(|(t, v)| v - slope * t).collect();
+    let intercept = stats::median(&residuals);
+    let (first, last) = year_range(anomalies);
+    let at = |t: f64| (t, intercept + slope * t);
+    [at(first as f64), at(last as f64)]
+}
+
+impl AnomalyBars {
+    pub fn spec(&self) -> ChartSpec {
+        let largest = self.anomalies.iter().map(|p| p.1.abs()).fold(0.0, f64::max);
+        let extent = units::difference(largest.max(0.1) * 1.15);
+        let (first, last) = year_range(&self.anomalies);
+        let x = Axis { range: first as f64..last as f64, description: "Year".to_string(), labels: 12, formatter: |year| format!("{:.0}", year) };
+        let y = Axis {
+            range: -extent..extent,
+            description: format!("Anomaly ({})", units::symbol()),
+            labels: 10,
+            formatter: |v| format!("{:+.1}", v),
+        };
+        let mut spec = ChartSpec::new(x, y);
+        spec.title = self.title.clone();
+        spec.theme = self.theme;
+        spec.grid = Grid::Horizontal;
+        let bars = self
+            .anomalies
+            .iter()
+            .map(|&(time, anomaly)| {
+                let year = time.floor();
+                (year + 0.1, year + 0.9, units::difference(anomaly), self.colour_map.diverging(anomaly, self.limit))
+            })
+            .collect();
+        spec.series.push(Series { name: String::new(), color: self.theme.foreground, data: SeriesData::Bars { bars } });
+
+        if let Some(trend) = &self.trend {
+            let line = trend_line(&self.anomalies, trend).map(|(t, v)| (t, units::difference(v)));
+            spec.series.push(Series {
+                name: format!(
+                    "Trend {:+.2} {}/decade (p = {:.3})",
+                    units::difference(trend.slope_per_decade),
+                    units::symbol(),
+                    trend.p_value
+                ),
+                color: self.theme.foreground,
+                data: SeriesData::Line { points: line.to_vec(), max_gap: None },
+            });
+            spec.legend = true;
+            spec.legend_position = SeriesLabelPosition::UpperLeft;
+        }
+        spec
+    }
+}
@@ -0,0 +1,367 @@
+use crate::daily::{self, DailyStats};
+use crate::stats;
+use crate::units;
+use crate::TempData;
+
+/*
+Long term trends. Daily means are aggregated into annual, seasonal or monthly
+anomaly series, then tested with the Mann-Kendall test. The modified test of
+Hamed and Rao (1998) inflates the variance to account for autocorrelation,
+which matters a lot for monthly anomalies since a warm month tends to be
+followed by another. The size of the trend is Sen's slope (median of every
+pairwise slope), reported in °C per decade with confidence bounds.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Season {
+    Winter, // December of the previous year, January, February
+    Spring, // March, April, May
+    Summer, // June, July, August
+    Autumn, // September, October, November
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aggregate {
+    Annual,
+    Seasonal(Season),
+    // Every month minus the mean of that calendar month over all years
+    MonthlyAnomaly,
+}
+
+// A point in a series: (time in years, eg. 2022.5, value in °C)
+pub type SeriesPoint = (f64, f64);
+
+#[derive(Clone, Copy, Debug)]
+pub struct TrendResult {
+    pub n: usize,
+    pub s: i64,
+    pub variance: f64, // Variance of S, after the autocorrelation correction if any
+    pub correction: f64, // n/n* from Hamed and Rao, 1 for the original test
+    pub z: f64,
+    pub p_value: f64, // Two sided
+    pub slope_per_decade: f64,
+    pub lower_per_decade: f64,
+    pub upper_per_decade: f64,
+    pub confidence: f64,
+}
+
+fn season_of_month(month: usize) -> Season {
+    match month {
+        12 | 1 | 2 => Season::Winter,
+        3..=5 => Season::Spring,
+        6..=8 => Season::Summer,
+        _ => Season::Autumn,
+    }
+}
+
+// Mean of the daily means of every month in every year: [year][month]. A
+// month needs at least `min_coverage` (0..1) of its days to have data,
+// otherwise it is None.
+fn monthly_means(years: &[Vec<TempData>], first_year: u32, min_coverage: f64) -> Vec<[Option<f64>; 12]> {
+    years
+        .iter()
+        .enumerate()
+        .map(|(i, temps)| {
+            let year = first_year + i as u32;
+            let days: Vec<Option<DailyStats>> = daily::daily_stats(temps);
+            let mut sums = [0.0; 12];
+            let mut present = [0usize; 12];
+            let mut lengths = [0usize; 12];
+            let days_in_year = if daily::is_leap_year(year) { 366 } else { 365 };
+            for (day_index, day) in days.iter().enumerate().take(days_in_year) {
+                let (month, _) = daily::month_and_day(year, day_index);
+                lengths[month - 1] += 1;
+                if let Some(day) = day {
+                    sums[month - 1] += day.mean;
+                    present[month - 1] += 1;
+                }
+            }
+            let mut means = [None; 12];
+            for month in 0..12 {
+                if present[month] as f64 >= min_coverage * lengths[month] as f64 && present[month] > 0 {
+                    means[month] = Some(sums[month] / present[month] as f64);
+                }
+            }
+            means
+        })
+        .collect()
+}
+
+// Builds the series that the trend tests run on. `years[0]` is `first_year`.
+// Annual and seasonal values are the mean of their monthly means and need
+// every month to be present.
+pub fn aggregate_series(
+    years: &[Vec<TempData>],
+    first_year: u32,
+    aggregate: Aggregate,
+    min_coverage: f64,
+) -> Vec<SeriesPoint> {
+    let months = monthly_means(years, first_year, min_coverage);
+    let mut series = Vec::new();
+    match aggregate {
+        Aggregate::Annual => {
+            for (i, year) in months.iter().enumerate() {
+                if let Some(values) = year.iter().copied().collect::<Option<Vec<f64>>>() {
+                    series.push((first_year as f64 + i as f64 + 0.5, stats::mean(&values)));
+                }
+            }
+        }
+        Aggregate::Seasonal(season) => {
+            for i in 0..months.len() {
+                let values: Option<Vec<f64>> = (1..=12)
+                    .filter(|&month| season_of_month(month) == season)
+                    .map(|month| {
+                        if month == 12 && season == Season::Winter {
+                            // December belongs to the following winter
+                            i.checked_sub(1).and_then(|previous| months[previous][11])
+                        } else {
+                            months[i][month - 1]
+                        }
+                    })
+                    .collect();
+                if let Some(values) = values {
+                    series.push((first_year as f64 + i as f64 + 0.5, stats::mean(&values)));
+                }
+            }
+        }
+        Aggregate::MonthlyAnomaly => {
+            let normals: Vec<f64> = (0..12)
+                .map(|month| {
+                    let values: Vec<f64> = months.iter().filter_map(|year| year[month]).collect();
+                    stats::mean(&values)
+                })
+                .collect();
+            for (i, year) in months.iter().enumerate() {
+                for (month, value) in year.iter().enumerate() {
+                    if let Some(value) = value {
+                        let time = first_year as f64 + i as f64 + (month as f64 + 0.5) / 12.0;
+                        series.push((time, value - normals[month]));
+                    }
+                }
+            }
+        }
+    }
+    series
+}
+
+// Subtracts the mean of the series from every value
+pub fn anomalies(series: &[SeriesPoint]) -> Vec<SeriesPoint> {
+    let values: Vec<f64> = series.iter().map(|p| p.1).collect();
+    let mean = stats::mean(&values);
+    series.iter().map(|&(t, v)| (t, v - mean)).collect()
+}
+
+// Every pairwise slope (°C per year), sorted
+fn pairwise_slopes(series: &[SeriesPoint]) -> Vec<f64> {
+    let mut slopes = Vec::with_capacity(series.len() * series.len() / 2);
+    for (i, a) in series.iter().enumerate() {
+        for b in &series[i + 1..] {
+            if b.0 != a.0 {
+                slopes.push((b.1 - a.1) / (b.0 - a.0));
+            }
+        }
+    }
+    slopes.sort_by(f64::total_cmp);
+    slopes
+}
+
+fn mann_kendall_s(values: &[f64]) -> i64 {
+    let mut s = 0;
+    for (i, a) in values.iter().enumerate() {
+        for b in &values[i + 1..] {
+            s += match b.total_cmp(a) {
+                std::cmp::Ordering::Greater => 1,
+                std::cmp::Ordering::Less => -1,
+                std::cmp::Ordering::Equal => 0,
+            };
+        }
+    }
+    s
+}
+
+// Variance of S including the correction for tied values
+fn mann_kendall_variance(values: &[f64]) -> f64 {
+    let n = values.len() as f64;
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let mut ties = 0.0;
+    for group in sorted.chunk_by(|a, b| a == b) {
+        let t = group.len() as f64;
+        ties += t * (t - 1.0) * (2.0 * t + 5.0);
+    }
+    (n * (n - 1.0) * (2.0 * n + 5.0) - ties) / 18.0
+}
+
+// Ranks (1 based, ties get the average rank)
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start;
+        while end + 1 < order.len() && values[order[end + 1]] == values[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end) as f64 / 2.0 + 1.0;
+        for &index in &order[start..=end] {
+            ranks[index] = rank;
+        }
+        start = end + 1;
+    }
+    ranks
+}
+
+// Hamed and Rao's variance correction factor n/n*. Only autocorrelations of
+// the detrended ranks which are significant at `confidence` are included.
+fn autocorrelation_correction(series: &[SeriesPoint], slope: f64, confidence: f64) -> f64 {
+    let n = series.len();
+    let detrended: Vec<f64> = series.iter().map(|&(t, v)| v - slope * t).collect();
+    let ranked = ranks(&detrended);
+    let limit = stats::normal_quantile(1.0 - (1.0 - confidence) / 2.0) / (n as f64).sqrt();
+    let nf = n as f64;
+    let mut sum = 0.0;
+    for lag in 1..n - 1 {
+        let r = stats::autocorrelation(&ranked, lag);
+        if r.abs() > limit {
+            let k = lag as f64;
+            sum += (nf - k) * (nf - k - 1.0) * (nf - k - 2.0) * r;
+        }
+    }
+    (1.0 + 2.0 / (nf * (nf - 1.0) * (nf - 2.0)) * sum).max(1e-3)
+}
+
+// Runs the Mann-Kendall test and computes Sen's slope. `series` must be
+// sorted by time. With `modified` the variance of S is corrected for
+// autocorrelation. `confidence` (eg. 0.95) applies to the slope bounds.
+pub fn mann_kendall(series: &[SeriesPoint], modified: bool, confidence: f64) -> Option<TrendResult> {
+    let n = series.len();
+    if n < 4 {
+        return None;
+    }
+    let values: Vec<f64> = series.iter().map(|p| p.1).collect();
+    let slopes = pairwise_slopes(series);
+    let slope = stats::median(&slopes);
+
+    let s = mann_kendall_s(&values);
+    let correction = if modified { autocorrelation_correction(series, slope, confidence) } else { 1.0 };
+    let variance = mann_kendall_variance(&values) * correction;
+    let z = match s {
+        s if s > 0 => (s - 1) as f64 / variance.sqrt(),
+        s if s < 0 => (s + 1) as f64 / variance.sqrt(),
+        _ => 0.0,
+    };
+    let p_value = 2.0 * (1.0 - stats::normal_cdf(z.abs()));
+
+    // Confidence bounds on the slope (Gilbert 1987)
+    let c = stats::normal_quantile(1.0 - (1.0 - confidence) / 2.0) * variance.sqrt();
+    let count = slopes.len() as f64;
+    let lower_index = (((count - c) / 2.0).round() as isize - 1).clamp(0, slopes.len() as isize - 1) as usize;
+    let upper_index = (((count + c) / 2.0).round() as isize).clamp(0, slopes.len() as isize - 1) as usize;
+
+    Some(TrendResult {
+        n,
+        s,
+        variance,
+        correction,
+        z,
+        p_value,
+        slope_per_decade: slope * 10.0,
+        lower_per_decade: slopes[lower_index] * 10.0,
+        upper_per_decade: slopes[upper_index] * 10.0,
+        confidence,
+    })
+}
+
+pub fn print_trend(station: &str, name: &str, trend: &TrendResult) {
+    println!(
+        "{} {}: {:+.2} {}/decade ({:.0}% CI {:+.2} to {:+.2}), S = {}, Var(S) = {:.1}, Z = {:.2}, p = {:.3}, n = {}{}",
+        station,
+        name,
+        units::difference(trend.slope_per_decade),
+        units::symbol(),
+        trend.confidence * 100.0,
+        units::difference(trend.lower_per_decade),
+        units::difference(trend.upper_per_decade),
+        trend.s,
+        trend.variance,
+        trend.z,
+        trend.p_value,
+        trend.n,
+        if trend.correction != 1.0 { format!(", n/n* = {:.2}", trend.correction) } else { String::new() }
+    );
+}
+
+// Answers "is `a` warming faster than `b`?". The difference only counts if the
+// confidence intervals of the two slopes don't overlap.
+pub fn print_trend_comparison(a: (&str, &TrendResult), b: (&str, &TrendResult)) {
+    let (name_a, trend_a) = a;
+    let (name_b, trend_b) = b;
+    print_trend(name_a, "trend", trend_a);
+    print_trend(name_b, "trend", trend_b);
+    let difference = trend_a.slope_per_decade - trend_b.slope_per_decade;
+    let (faster, slower) = if difference >= 0.0 { (a, b) } else { (b, a) };
+    if faster.1.lower_per_decade > slower.1.upper_per_decade {
+        println!(
+            "{} is warming significantly faster than {} ({:+.2} {}/decade)",
+            faster.0,
+            slower.0,
+            units::difference(difference.abs()),
+            units::symbol()
+        );
+    } else {
+        println!(
+            "{} is warming {:.2} {}/decade faster than {}, but the difference is not significant",
+            faster.0,
+            units::difference(difference.abs()),
+            units::symbol(),
+            slower.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yearly(values: &[f64]) -> Vec<SeriesPoint> {
+        values.iter().enumerate().map(|(i, &v)| (2000.0 + i as f64, v)).collect()
+    }
+
+    #[test]
+    fn mann_kendall_matches_a_worked_example() {
+        // 25 of the 28 pairs increase and 3 decrease: S = 25 - 3 = 22,
+        // Var(S) = n(n-1)(2n+5)/18 = 65.33, Z = (S-1)/sqrt(Var(S)) = 2.598
+        let series = yearly(&[1.0, 3.0, 2.0, 5.0, 4.0, 6.0, 8.0, 7.0]);
+        let result = mann_kendall(&series, false, 0.95).unwrap();
+        assert_eq!(result.s, 22);
+        assert!((result.variance - 65.3333).abs() < 1e-3);
+        assert!((result.z - 2.5981).abs() < 1e-3);
+        assert!((result.p_value - 0.00937).abs() < 1e-4);
+        // Sen's slope is the median of the 28 pairwise slopes. With
+        // C = 1.96 * sqrt(Var(S)) = 15.84 the bounds are the 6th smallest
+        // and the 23rd smallest slope (Gilbert 1987).
+        assert!((result.slope_per_decade - 10.0).abs() < 1e-9);
+        assert!((result.lower_per_decade - 5.0).abs() < 1e-9);
+        assert!((result.upper_per_decade - 15.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ties_reduce_the_variance_of_s() {
+        // Groups of 2, 2 and 3 tied values remove (2*1*9 + 2*1*9 + 3*2*11)/18
+        let series = yearly(&[1.0, 2.0, 2.0, 3.0, 3.0, 3.0, 4.0, 1.0]);
+        let result = mann_kendall(&series, false, 0.95).unwrap();
+        assert_eq!(result.s, 11);
+        assert!((result.variance - 59.6667).abs() < 1e-3);
+    }
+
+    #[test]
+    fn no_trend_in_a_symmetric_series() {
+        let series = yearly(&[1.0, 2.0, 3.0, 4.0, 3.0, 2.0, 1.0]);
+        let result = mann_kendall(&series, false, 0.95).unwrap();
+        assert_eq!(result.s, 0);
+        assert_eq!(result.z, 0.0);
+        assert!((result.p_value - 1.0).abs() < 1e-6);
+        assert!(result.lower_per_decade < 0.0 && result.upper_per_decade > 0.0);
+    }
+}
@@ -0,0 +1,190 @@
+use std::marker::PhantomData;
+use std::sync::atomic::{self, AtomicU8};
+
+/*
+Temperature units. Everything is stored and computed in °C; ISD reports tenths
+of a degree Celsius, which is what Tenths<Celsius> holds. The unit things are
+shown in (statistics output, exports and chart axes) is a single global
+setting, chosen once with set_unit().
+
+Absolute temperatures and temperature differences convert differently: 20 °C
+is 68 °F, but a 20 °C swing is a 36 °F swing. Anything that is a difference
+(anomalies, standard deviations, trends, degree days, ...) must go through
+difference() rather than temperature(). Values read in the chosen unit, eg.
+from the command line, come back to °C with from_display() and
+difference_from_display().
+*/
+
+pub trait Scale {
+    const SYMBOL: &'static str;
+    fn from_celsius(celsius: f64) -> f64;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Celsius;
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Fahrenheit;
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Kelvin;
+
+impl Scale for Celsius {
+    const SYMBOL: &'static str = "°C";
+    fn from_celsius(celsius: f64) -> f64 {
+        celsius
+    }
+}
+
+impl Scale for Fahrenheit {
+    const SYMBOL: &'static str = "°F";
+    fn from_celsius(celsius: f64) -> f64 {
+        celsius * 9.0 / 5.0 + 32.0
+    }
+}
+
+impl Scale for Kelvin {
+    const SYMBOL: &'static str = "K";
+    fn from_celsius(celsius: f64) -> f64 {
+        celsius + 273.15
+    }
+}
+
+// A temperature in tenths of a degree of the scale S
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Tenths<S: Scale>(i16, PhantomData<S>);
+
+impl<S: Scale> Tenths<S> {
+    pub const fn new(tenths: i16) -> Tenths<S> {
+        Tenths(tenths, PhantomData)
+    }
+
+    // Rounded to the nearest tenth
+    pub fn from_degrees(degrees: f64) -> Tenths<S> {
+        Tenths::new((degrees * 10.0).round() as i16)
+    }
+
+    pub fn tenths(self) -> i16 {
+        self.0
+    }
+
+    pub fn degrees(self) -> f64 {
+        self.0 as f64 / 10.0
+    }
+}
+
+impl Tenths<Celsius> {
+    // In the unit chosen with set_unit()
+    pub fn display(self) -> f64 {
+        temperature(self.degrees())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unit {
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+impl Unit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::Celsius => Celsius::SYMBOL,
+            Unit::Fahrenheit => Fahrenheit::SYMBOL,
+            Unit::Kelvin => Kelvin::SYMBOL,
+        }
+    }
+
+    pub fn temperature(&self, celsius: f64) -> f64 {
+        match self {
+            Unit::Celsius => Celsius::from_celsius(celsius),
+            Unit::Fahrenheit => Fahrenheit::from_celsius(celsius),
+            Unit::Kelvin => Kelvin::from_celsius(celsius),
+        }
+    }
+
+    // Size of a difference of `celsius` degrees in this unit
+    pub fn difference(&self, celsius: f64) -> f64 {
+        match self {
+            Unit::Fahrenheit => celsius * 9.0 / 5.0,
+            Unit::Celsius | Unit::Kelvin => celsius,
+        }
+    }
+
+    // Inverse of temperature(): °C for `degrees` in this unit
+    pub fn celsius(&self, degrees: f64) -> f64 {
+        match self {
+            Unit::Celsius => degrees,
+            Unit::Fahrenheit => (degrees - 32.0) * 5.0 / 9.0,
+            Unit::Kelvin => degrees - 273.15,
+        }
+    }
+
+    // Inverse of difference()
+    pub fn celsius_difference(&self, degrees: f64) -> f64 {
+        match self {
+            Unit::Fahrenheit => degrees * 5.0 / 9.0,
+            Unit::Celsius | Unit::Kelvin => degrees,
+        }
+    }
+}
+
+static UNIT: AtomicU8 = AtomicU8::new(0);
+
+pub fn set_unit(unit: Unit) {
+    UNIT.store(unit as u8, atomic::Ordering::Relaxed);
+}
+
+pub fn unit() -> Unit {
+    match UNIT.load(atomic::Ordering::Relaxed) {
+        1 => Unit::Fahrenheit,
+        2 => Unit::Kelvin,
+        _ => Unit::Celsius,
+    }
+}
+
+// Shorthands for the global unit
+
+pub fn symbol() -> &'static str {
+    unit().symbol()
+}
+
+pub fn squared_symbol() -> &'static str {
+    match unit() {
+        Unit::Celsius => "°C²",
+        Unit::Fahrenheit => "°F²",
+        Unit::Kelvin => "K²",
+    }
+}
+
+pub fn temperature(celsius: f64) -> f64 {
+    unit().temperature(celsius)
+}
+
+pub fn difference(celsius: f64) -> f64 {
+    unit().difference(celsius)
+}
+
+pub fn from_display(degrees: f64) -> f64 {
+    unit().celsius(degrees)
+}
+
+pub fn difference_from_display(degrees: f64) -> f64 {
+    unit().celsius_difference(degrees)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_back_from_the_display_unit() {
+        for unit in [Unit::Celsius, Unit::Fahrenheit, Unit::Kelvin] {
+            for celsius in [-40.0, 0.0, 18.3, 37.5] {
+                assert!((unit.celsius(unit.temperature(celsius)) - celsius).abs() < 1e-9);
+                assert!((unit.celsius_difference(unit.difference(celsius)) - celsius).abs() < 1e-9);
+            }
+        }
+        assert_eq!(Unit::Fahrenheit.celsius(212.0), 100.0);
+        assert_eq!(Unit::Fahrenheit.celsius_difference(9.0), 5.0);
+    }
+}
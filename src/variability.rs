@@ -0,0 +1,248 @@
+use crate::daily;
+use crate::isd::{ExtremeKind, ExtremeTemp};
+use crate::stats;
+use crate::units;
+use crate::TempData;
+
+/*
+Diurnal temperature range (DTR) and day-to-day variability. Strange weather is
+often about swings rather than averages: two cities with the same mean and
+standard deviation can feel very different if one of them flips between warm
+and cold every other day.
+*/
+
+#[derive(Clone, Copy, Debug)]
+pub struct DailyRange {
+    pub max: f64,
+    pub min: f64,
+    pub range: f64,
+    pub mean: f64, // Duration weighted
+}
+
+// A change in temperature over 24 hours, ending at `minute_of_year`
+#[derive(Clone, Copy, Debug)]
+pub struct Swing {
+    pub minute_of_year: u32,
+    pub change: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct VariabilityReport {
+    pub mean_range: f64,
+    pub range_standard_deviation: f64,
+    pub largest_range: Option<(usize, f64)>, // (local day index, °C)
+    pub mean_abs_change: f64, // Mean |ΔTmean| between consecutive days
+    pub change_standard_deviation: f64,
+    pub largest_swings: Vec<Swing>,
+    pub whiplash_count: usize,
+}
+
+// Minute of the year in local standard time, None if that falls outside the
+// year
+fn local_minute(minute_of_year: u32, utc_offset: i32) -> Option<u32> {
+    let local = minute_of_year as i64 + utc_offset as i64;
+    (0..366 * 1440).contains(&local).then_some(local as u32)
+}
+
+// Daily max, min and range, for local days (`utc_offset` in minutes). The max
+// and min come from the observations themselves, widened by any KA1-KA4
+// extreme temperature groups since the hourly observations usually miss the
+// true peak. Extreme groups are assigned to the day containing the middle of
+// the period they cover.
+pub fn daily_ranges(temps: &[TempData], extremes: &[ExtremeTemp], utc_offset: i32) -> Vec<Option<DailyRange>> {
+    let local: Vec<TempData> = temps
+        .iter()
+        .filter_map(|t| {
+            let minute_of_year = local_minute(t.minute_of_year, utc_offset)?;
+            Some(TempData { temp10: t.temp10, duration: t.duration, minute_of_year })
+        })
+        .collect();
+    let mut days: Vec<Option<DailyRange>> = daily::daily_stats(&local)
+        .iter()
+        .map(|day| day.map(|d| DailyRange { max: d.max, min: d.min, range: d.max - d.min, mean: d.mean }))
+        .collect();
+    for extreme in extremes {
+        let Some(midpoint) = extreme.minute_of_year.checked_sub(extreme.period / 2).and_then(|m| local_minute(m, utc_offset)) else {
+            continue;
+        };
+        let Some(Some(day)) = days.get_mut(crate::get_day_index_from_minutes(midpoint)) else {
+            continue;
+        };
+        let t = extreme.temp10.degrees();
+        match extreme.kind {
+            ExtremeKind::Max => day.max = day.max.max(t),
+            ExtremeKind::Min => day.min = day.min.min(t),
+        }
+        day.range = day.max - day.min;
+    }
+    days
+}
+
+// Temperature in effect at `minute`, using the same step-hold rule as the
+// durations in TempData. `temps` must be sorted by time.
+fn value_at(temps: &[TempData], minute: u32) -> Option<f64> {
+    let index = temps.partition_point(|t| t.minute_of_year <= minute).checked_sub(1)?;
+    let temp = &temps[index];
+    if minute < temp.minute_of_year + temp.duration as u32 {
+        Some(temp.temp10.degrees())
+    } else {
+        None // In a gap
+    }
+}
+
+// The `count` largest changes over 24 hours. Swings within a day of a larger
+// swing are considered part of the same event and skipped.
+pub fn largest_swings(temps: &[TempData], count: usize) -> Vec<Swing> {
+    let mut swings: Vec<Swing> = temps
+        .iter()
+        .filter(|t| t.minute_of_year >= 1440)
+        .filter_map(|t| {
+            let before = value_at(temps, t.minute_of_year - 1440)?;
+            Some(Swing { minute_of_year: t.minute_of_year, change: t.temp10.degrees() - before })
+        })
+        .collect();
+    swings.sort_by(|a, b| b.change.abs().total_cmp(&a.change.abs()));
+    let mut largest: Vec<Swing> = Vec::with_capacity(count);
+    for swing in swings {
+        if largest.len() == count {
+            break;
+        }
+        if largest.iter().all(|l| l.minute_of_year.abs_diff(swing.minute_of_year) >= 1440) {
+            largest.push(swing);
+        }
+    }
+    largest
+}
+
+// Whiplash: a change of at least `threshold` °C in the daily mean which is
+// immediately followed by a change of at least `threshold` °C the other way.
+pub fn whiplash_count(days: &[Option<DailyRange>], threshold: f64) -> usize {
+    let changes = day_to_day_changes(days);
+    changes
+        .windows(2)
+        .filter(|pair| match (pair[0], pair[1]) {
+            (Some(a), Some(b)) => a.abs() >= threshold && b.abs() >= threshold && a.signum() != b.signum(),
+            _ => false,
+        })
+        .count()
+}
+
+// Change in the daily mean from one day to the next, None if either day is
+// missing.
+fn day_to_day_changes(days: &[Option<DailyRange>]) -> Vec<Option<f64>> {
+    days.windows(2)
+        .map(|pair| match (pair[0], pair[1]) {
+            (Some(a), Some(b)) => Some(b.mean - a.mean),
+            _ => None,
+        })
+        .collect()
+}
+
+pub fn variability_report(temps: &[TempData], extremes: &[ExtremeTemp], utc_offset: i32, whiplash_threshold: f64) -> VariabilityReport {
+    let days = daily_ranges(temps, extremes, utc_offset);
+    let ranges: Vec<f64> = days.iter().flatten().map(|d| d.range).collect();
+    let largest_range = days
+        .iter()
+        .enumerate()
+        .filter_map(|(i, d)| d.map(|d| (i, d.range)))
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+    let changes: Vec<f64> = day_to_day_changes(&days).into_iter().flatten().collect();
+    let abs_changes: Vec<f64> = changes.iter().map(|c| c.abs()).collect();
+    VariabilityReport {
+        mean_range: stats::mean(&ranges),
+        range_standard_deviation: stats::variance(&ranges).sqrt(),
+        largest_range,
+        mean_abs_change: stats::mean(&abs_changes),
+        change_standard_deviation: stats::variance(&changes).sqrt(),
+        largest_swings: largest_swings(temps, 5),
+        whiplash_count: whiplash_count(&days, whiplash_threshold),
+    }
+}
+
+pub fn print_variability_report(station: &str, year: u32, report: &VariabilityReport) {
+    println!("Variability at {} in {}:", station, year);
+    let symbol = units::symbol();
+    println!(
+        "  Diurnal range: {:.1} ± {:.1} {}",
+        units::difference(report.mean_range),
+        units::difference(report.range_standard_deviation),
+        symbol
+    );
+    if let Some((day, range)) = report.largest_range {
+        println!("  Largest diurnal range: {:.1} {} on {}", units::difference(range), symbol, daily::format_date(year, day));
+    }
+    println!(
+        "  Day to day change in mean: |Δ| = {:.2} {}, SD {:.2} {}",
+        units::difference(report.mean_abs_change),
+        symbol,
+        units::difference(report.change_standard_deviation),
+        symbol
+    );
+    println!("  Whiplash days: {}", report.whiplash_count);
+    println!("  Largest 24 hour swings:");
+    for swing in &report.largest_swings {
+        let day = crate::get_day_index_from_minutes(swing.minute_of_year);
+        let minute_of_day = swing.minute_of_year % 1440;
+        println!(
+            "    {:+.1} {} ending {} {:02}:{:02} UTC",
+            units::difference(swing.change),
+            symbol,
+            daily::format_date(year, day),
+            minute_of_day / 60,
+            minute_of_day % 60
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Tenths;
+
+    fn temp(minute_of_year: u32, degrees: f64) -> TempData {
+        TempData { temp10: Tenths::from_degrees(degrees), duration: 60, minute_of_year }
+    }
+
+    fn day(mean: f64) -> Option<DailyRange> {
+        Some(DailyRange { max: mean, min: mean, range: 0.0, mean })
+    }
+
+    #[test]
+    fn ranges_are_for_local_days() {
+        // 03:00 UTC on January 2nd is still January 1st six hours behind UTC
+        let temps = [temp(1440 + 180, 5.0), temp(1440 + 420, 15.0)];
+        let utc = daily_ranges(&temps, &[], 0);
+        assert!(utc[0].is_none());
+        assert_eq!(utc[1].unwrap().range, 10.0);
+        let local = daily_ranges(&temps, &[], -360);
+        assert_eq!((local[0].unwrap().max, local[1].unwrap().min), (5.0, 15.0));
+        // Observations moved out of the year are dropped
+        assert!(daily_ranges(&[temp(60, 5.0)], &[], -360).iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn largest_swings_skip_the_rest_of_the_same_event() {
+        // 10 °C for two days, 30 °C for a week, then 0 °C
+        let temps: Vec<TempData> = (0..20 * 24)
+            .map(|hour| temp(hour * 60, match hour / 24 { 0..=1 => 10.0, 2..=8 => 30.0, _ => 0.0 }))
+            .collect();
+        let swings = largest_swings(&temps, 3);
+        assert_eq!((swings[0].minute_of_year, swings[0].change), (9 * 1440, -30.0));
+        assert_eq!((swings[1].minute_of_year, swings[1].change), (2 * 1440, 20.0));
+        // Every hour of the first day after each change is as large, but only
+        // the first of them is kept
+        assert_eq!(swings[2].change, 0.0);
+        for (i, a) in swings.iter().enumerate() {
+            assert!(swings[i + 1..].iter().all(|b| a.minute_of_year.abs_diff(b.minute_of_year) >= 1440));
+        }
+    }
+
+    #[test]
+    fn whiplash_needs_two_large_changes_in_opposite_directions() {
+        let days = [day(10.0), day(20.0), day(10.0), day(20.0), day(21.0), None, day(10.0), day(20.0), day(25.0)];
+        // 10 -> 20 -> 10 -> 20 twice; the gap and the changes in the same
+        // direction don't count
+        assert_eq!(whiplash_count(&days, 5.0), 2);
+        assert_eq!(whiplash_count(&days, 10.5), 0);
+    }
+}
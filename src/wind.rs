@@ -0,0 +1,211 @@
+use crate::isd::{self, Wind};
+use crate::stats;
+
+/*
+Wind statistics from the mandatory section's wind direction and speed, plus
+peak gusts from the OC1 group. Like temperatures, every observation is
+weighted by how long it stands for (minutes until the next one), capped so
+that a gap in the record doesn't hand days to a single reading. Speeds are in
+m/s, as ISD reports them.
+    - wind_rose: how much of the time the wind blows from each direction,
+      split into speed classes, plus the share of calm and of variable wind.
+    - wind_stats: calm share, mean and highest wind, prevailing direction,
+      how steady the direction is, and gust statistics.
+The rose is drawn as a polar chart by chart::WindRoseChart.
+*/
+
+// Longest time (minutes) a single observation can stand for
+const MAX_DURATION: u32 = 180;
+
+// Winds slower than this (m/s) count as calm
+pub const CALM_BELOW: f64 = 0.5;
+
+// Upper bounds (m/s) of the speed classes above calm, the last class being
+// open ended. These are the usual classes of US EPA wind roses.
+pub const SPEED_CLASSES: [f64; 5] = [2.1, 3.6, 5.7, 8.8, 11.1];
+
+const COMPASS_POINTS: [&str; 16] =
+    ["N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW", "NNW"];
+
+#[derive(Clone, Copy, Debug)]
+pub struct WindObservation {
+    pub wind: Wind,
+    pub gust: Option<f64>, // m/s
+    pub duration: u32, // Minutes
+}
+
+// Every record with a valid wind, in time order
+pub fn extract_wind(raw_data: &[String]) -> Vec<WindObservation> {
+    let winds: Vec<(Wind, Option<f64>)> = raw_data.iter().filter_map(|line| Some((isd::wind(line)?, isd::gust(line)))).collect();
+    winds
+        .iter()
+        .enumerate()
+        .map(|(i, &(wind, gust))| {
+            let next = winds.get(i + 1).map_or(wind.minute_of_year + 60, |w| w.0.minute_of_year);
+            let duration = next.saturating_sub(wind.minute_of_year).min(MAX_DURATION);
+            WindObservation { wind, gust, duration }
+        })
+        .collect()
+}
+
+// Name of the compass point closest to `degrees`
+pub fn compass_point(degrees: f64) -> &'static str {
+    COMPASS_POINTS[((degrees.rem_euclid(360.0) / 22.5).round() as usize) % 16]
+}
+
+#[derive(Clone, Debug)]
+pub struct WindRose {
+    pub calm_below: f64, // m/s
+    pub classes: Vec<f64>, // Upper bounds of the speed classes, m/s
+    pub frequencies: Vec<Vec<f64>>, // [sector][class], % of the time. Sector 0 is centred on north.
+    pub calm: f64, // % of the time
+    pub variable: f64, // % of the time with wind but no direction
+}
+
+impl WindRose {
+    pub fn sectors(&self) -> usize {
+        self.frequencies.len()
+    }
+}
+
+fn speed_class(speed: f64, classes: &[f64]) -> usize {
+    classes.iter().position(|&bound| speed < bound).unwrap_or(classes.len())
+}
+
+// Frequencies of wind from `sectors` directions (eg. 16) by speed class
+pub fn wind_rose(observations: &[WindObservation], sectors: usize, calm_below: f64, classes: &[f64]) -> WindRose {
+    let classes = classes.to_vec();
+    let mut frequencies = vec![vec![0.0; classes.len() + 1]; sectors];
+    let (mut calm, mut variable) = (0.0, 0.0);
+    let total: f64 = observations.iter().map(|o| o.duration as f64).sum::<f64>().max(1.0);
+    for observation in observations {
+        let share = observation.duration as f64 / total * 100.0;
+        let wind = observation.wind;
+        if wind.calm || wind.speed < calm_below {
+            calm += share;
+            continue;
+        }
+        let Some(direction) = wind.direction else {
+            variable += share;
+            continue;
+        };
+        let width = 360.0 / sectors as f64;
+        let sector = ((direction as f64 / width).round() as usize) % sectors;
+        frequencies[sector][speed_class(wind.speed, &classes)] += share;
+    }
+    WindRose { calm_below, classes, frequencies, calm, variable }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GustStats {
+    pub count: usize, // Observations reporting a gust
+    pub days: usize, // Days with at least one gust
+    pub mean: f64, // m/s
+    pub max: f64,
+    pub percentile_95: f64,
+    pub gust_factor: f64, // Mean ratio of gust to the wind speed of the same observation
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WindStats {
+    pub calm: f64, // % of the time
+    pub mean_speed: f64, // m/s, including calms
+    pub max_speed: f64,
+    pub prevailing: Option<f64>, // Degrees, the centre of the most frequent 16-point sector
+    pub resultant_direction: Option<f64>, // Degrees, direction of the mean wind vector
+    pub steadiness: f64, // Length of the mean unit wind vector, 0 (all over) to 1 (constant)
+    pub gusts: Option<GustStats>,
+}
+
+pub fn wind_stats(observations: &[WindObservation]) -> WindStats {
+    let weight = |o: &WindObservation| o.duration as f64;
+    let total: f64 = observations.iter().map(weight).sum::<f64>().max(1.0);
+    let mean_speed = observations.iter().map(|o| o.wind.speed * weight(o)).sum::<f64>() / total;
+    let max_speed = observations.iter().map(|o| o.wind.speed).fold(0.0, f64::max);
+
+    let rose = wind_rose(observations, 16, CALM_BELOW, &[]);
+    let sector_totals: Vec<f64> = rose.frequencies.iter().map(|classes| classes.iter().sum()).collect();
+    let prevailing = sector_totals
+        .iter()
+        .enumerate()
+        .filter(|(_, &share)| share > 0.0)
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map(|(sector, _)| sector as f64 * 22.5);
+
+    // Unit vectors pointing where the wind comes from
+    let (mut east, mut north, mut directed) = (0.0, 0.0, 0.0);
+    for observation in observations {
+        if let Some(direction) = observation.wind.direction {
+            let radians = (direction as f64).to_radians();
+            east += radians.sin() * weight(observation);
+            north += radians.cos() * weight(observation);
+            directed += weight(observation);
+        }
+    }
+    let steadiness = if directed > 0.0 { (east * east + north * north).sqrt() / directed } else { 0.0 };
+    let resultant_direction = (directed > 0.0 && steadiness > 1e-9).then(|| east.atan2(north).to_degrees().rem_euclid(360.0));
+
+    let gusts: Vec<(&WindObservation, f64)> = observations.iter().filter_map(|o| Some((o, o.gust?))).collect();
+    let gust_stats = (!gusts.is_empty()).then(|| {
+        let speeds: Vec<f64> = gusts.iter().map(|g| g.1).collect();
+        let mut days: Vec<u32> = gusts.iter().map(|g| g.0.wind.minute_of_year / 1440).collect();
+        days.dedup();
+        let factors: Vec<f64> = gusts.iter().filter(|g| g.0.wind.speed > 0.0).map(|g| g.1 / g.0.wind.speed).collect();
+        GustStats {
+            count: gusts.len(),
+            days: days.len(),
+            mean: stats::mean(&speeds),
+            max: speeds.iter().copied().fold(0.0, f64::max),
+            percentile_95: stats::quantile(&speeds, 0.95),
+            gust_factor: stats::mean(&factors),
+        }
+    });
+
+    WindStats { calm: rose.calm, mean_speed, max_speed, prevailing, resultant_direction, steadiness, gusts: gust_stats }
+}
+
+pub fn print_wind_stats(station: &str, stats: &WindStats) {
+    println!("{} wind:", station);
+    println!("    Calm {:.1}% of the time", stats.calm);
+    println!("    Mean speed {:.1} m/s, highest {:.1} m/s", stats.mean_speed, stats.max_speed);
+    if let Some(prevailing) = stats.prevailing {
+        println!("    Prevailing direction {} ({:.0}°)", compass_point(prevailing), prevailing);
+    }
+    if let Some(resultant) = stats.resultant_direction {
+        println!("    Resultant direction {} ({:.0}°), steadiness {:.2}", compass_point(resultant), resultant, stats.steadiness);
+    }
+    match stats.gusts {
+        Some(gusts) => println!(
+            "    Gusts: {} reports on {} days, mean {:.1} m/s, 95th percentile {:.1} m/s, highest {:.1} m/s, gust factor {:.2}",
+            gusts.count, gusts.days, gusts.mean, gusts.percentile_95, gusts.max, gusts.gust_factor
+        ),
+        None => println!("    No gusts reported"),
+    }
+}
+
+pub fn class_name(rose: &WindRose, class: usize) -> String {
+    let low = if class == 0 { rose.calm_below } else { rose.classes[class - 1] };
+    match rose.classes.get(class) {
+        Some(high) => format!("{:.1}-{:.1}", low, high),
+        None => format!(">= {:.1}", low),
+    }
+}
+
+// Frequency table of the rose: one row per direction, one column per speed
+// class, in % of the time
+pub fn print_wind_rose(station: &str, rose: &WindRose) {
+    println!("{} wind rose (% of the time, speeds in m/s)", station);
+    print!("{:<6}", "Dir");
+    for class in 0..=rose.classes.len() {
+        print!("{:>11}", class_name(rose, class));
+    }
+    println!("{:>9}", "Total");
+    for (sector, classes) in rose.frequencies.iter().enumerate() {
+        print!("{:<6}", compass_point(sector as f64 * 360.0 / rose.sectors() as f64));
+        for share in classes {
+            print!("{:>11.1}", share);
+        }
+        println!("{:>9.1}", classes.iter().sum::<f64>());
+    }
+    println!("Calm {:.1}%, variable {:.1}%", rose.calm, rose.variable);
+}